@@ -24,6 +24,7 @@ def update():
                     'url': 'https://example.com/'
                      }],
         'party': [1, 3], # 'party': [current party size, max party size],
+        'activity_type': 'playing', # one of 'playing', 'listening', 'watching', 'competing'
     }]
 
 while True:
@@ -0,0 +1,72 @@
+/*
+    Copyright © 2021-2022 trickybestia <trickybestia@gmail.com>
+
+    This file is part of linux-discord-rich-presence.
+
+    linux-discord-rich-presence is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    linux-discord-rich-presence is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::time::Duration;
+
+/// Exponential backoff policy for reconnection attempts.
+#[derive(Clone, Copy)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub multiplier: f64,
+    /// Fraction of the delay which is randomly added to or subtracted from it.
+    pub jitter: f64,
+}
+
+impl Backoff {
+    /// Returns delay before the next attempt after `failures` consecutive failed ones.
+    pub fn delay(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay = (self.initial.as_secs_f64() * self.multiplier.powi(exponent))
+            .min(self.max.as_secs_f64());
+        let jitter = self.jitter * (fastrand::f64() * 2.0 - 1.0);
+
+        Duration::try_from_secs_f64((delay * (1.0 + jitter)).max(0.0)).unwrap_or(self.max)
+    }
+}
+
+/// Parses durations given in seconds, like `--backoff-initial` and `--template-interval`.
+pub fn parse_seconds(value: &str) -> Result<Duration, String> {
+    let seconds = value.parse::<f64>().map_err(|err| err.to_string())?;
+
+    Duration::try_from_secs_f64(seconds).map_err(|err| err.to_string())
+}
+
+/// Parses `--backoff-multiplier`. Delays mustn't shrink, otherwise failed connections would
+/// be retried in a busy loop.
+pub fn parse_multiplier(value: &str) -> Result<f64, String> {
+    let multiplier = value.parse::<f64>().map_err(|err| err.to_string())?;
+
+    if multiplier >= 1.0 {
+        Ok(multiplier)
+    } else {
+        Err("must be at least 1".to_owned())
+    }
+}
+
+/// Parses `--backoff-jitter`. Jitter of 1 or more could make delays zero.
+pub fn parse_jitter(value: &str) -> Result<f64, String> {
+    let jitter = value.parse::<f64>().map_err(|err| err.to_string())?;
+
+    if (0.0..1.0).contains(&jitter) {
+        Ok(jitter)
+    } else {
+        Err("must be at least 0 and less than 1".to_owned())
+    }
+}
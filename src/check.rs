@@ -0,0 +1,176 @@
+/*
+    Copyright © 2021-2022 trickybestia <trickybestia@gmail.com>
+
+    This file is part of linux-discord-rich-presence.
+
+    linux-discord-rich-presence is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    linux-discord-rich-presence is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use is_executable::is_executable;
+use tokio::time::timeout_at;
+
+use crate::{
+    backoff::parse_seconds,
+    process_wrapper::ProcessWrapper,
+    rich_presence_config::load_config,
+    update_message::{self, SourceMessage, Update},
+    validation::validate,
+};
+
+/// Checks a config against Discord's limits without connecting to Discord
+#[derive(clap::Args)]
+pub struct CheckArgs {
+    /// Path to the config file
+    config: PathBuf,
+    /// Count of update messages to read from an executable config
+    #[clap(long, default_value_t = 1)]
+    lines: usize,
+    /// Time to wait for update messages from an executable config, in seconds
+    #[clap(long, default_value = "10", value_name = "SECONDS", value_parser = parse_seconds)]
+    timeout: Duration,
+}
+
+/// Prints violations of Discord's limits in `message`. Returns `false` if there are any.
+fn report(number: usize, message: &SourceMessage) -> bool {
+    let mut violations = Vec::new();
+
+    for item in &message.activities {
+        for violation in validate(item) {
+            violations.push(format!(
+                "  Application {}: {}.",
+                item.application_id, violation
+            ));
+        }
+    }
+
+    if violations.is_empty() {
+        println!(
+            "Message {}: OK ({} activities).",
+            number,
+            message.activities.len()
+        );
+    } else {
+        println!("Message {}:", number);
+
+        for violation in &violations {
+            println!("{}", violation);
+        }
+    }
+
+    violations.is_empty()
+}
+
+async fn check_executable(args: CheckArgs) -> bool {
+    let mut process = match ProcessWrapper::new(&args.config).await {
+        Ok(process) => process,
+        Err(err) => {
+            println!("Error while starting Config Process: {}", err);
+
+            return false;
+        }
+    };
+    let deadline = Instant::now() + args.timeout;
+    let mut message = SourceMessage::default();
+    let mut is_valid = true;
+
+    for number in 1..=args.lines {
+        // Config Process which sent fewer messages than requested isn't wrong by itself, unless
+        // it sent none.
+        let line = match timeout_at(deadline.into(), process.read_line()).await {
+            Ok(Ok(Some(line))) => line,
+            Ok(Ok(None)) => {
+                println!(
+                    "Config Process exited after sending {} of {} messages.",
+                    number - 1,
+                    args.lines
+                );
+
+                return is_valid && number > 1;
+            }
+            Ok(Err(err)) => {
+                println!("Error while reading Config Process' stdout: `{}`.", err);
+
+                return false;
+            }
+            Err(_) => {
+                println!(
+                    "Config Process sent {} of {} messages in {:.1} seconds.",
+                    number - 1,
+                    args.lines,
+                    args.timeout.as_secs_f64()
+                );
+
+                return is_valid && number > 1;
+            }
+        };
+
+        match update_message::parse(&line) {
+            Ok(Update::Message(new_message)) => message = new_message,
+            Ok(Update::Patch(patch)) => {
+                if let Err(err) = message.apply(patch) {
+                    println!("Message {}: Error while applying patch: `{}`.", number, err);
+
+                    is_valid = false;
+
+                    continue;
+                }
+            }
+            Err(err) => {
+                println!(
+                    "Message {}: Error while parsing: `{}`. Received value: `{}`.",
+                    number, err, line
+                );
+
+                is_valid = false;
+
+                continue;
+            }
+        }
+
+        is_valid &= report(number, &message);
+    }
+
+    is_valid
+}
+
+/// Checks the config, printing a report. Returns `false` if the config is invalid.
+pub async fn check(args: CheckArgs) -> bool {
+    println!("Checking `{}`.", args.config.display());
+
+    let is_valid = if is_executable(&args.config) {
+        check_executable(args).await
+    } else {
+        match load_config(&args.config).await {
+            Ok((message, _)) => report(1, &message),
+            Err(err) => {
+                println!("{}", err);
+
+                false
+            }
+        }
+    };
+
+    if is_valid {
+        println!("Config is valid.");
+    } else {
+        println!("Config is invalid.");
+    }
+
+    is_valid
+}
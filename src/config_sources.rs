@@ -0,0 +1,254 @@
+/*
+    Copyright © 2021-2022 trickybestia <trickybestia@gmail.com>
+
+    This file is part of linux-discord-rich-presence.
+
+    linux-discord-rich-presence is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    linux-discord-rich-presence is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use log::{error, info};
+use serde::Serialize;
+use tokio::{
+    select,
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+};
+
+use crate::{
+    config_watcher::ConfigWatcher,
+    rich_presence_config::{RestartPolicy, RichPresenceConfig},
+    stdin_message::StdinMessage,
+    update_message::{MergeMode, SourceMessage, Update, UpdateMessage, UpdateMessageItem},
+};
+
+/// Where update messages come from. At the same priority, activities pushed at runtime take
+/// precedence over the ones of config files.
+#[derive(Serialize, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    File(PathBuf),
+    ControlSocket,
+    Dbus,
+}
+
+/// Update message together with the config file it came from.
+pub struct SourceUpdate {
+    pub source: Source,
+    pub message: Update,
+}
+
+/// Sends update messages of a single config file.
+#[derive(Clone)]
+pub struct SourceSender {
+    source: PathBuf,
+    sender: mpsc::Sender<SourceUpdate>,
+}
+
+impl SourceSender {
+    pub fn new(source: PathBuf, sender: mpsc::Sender<SourceUpdate>) -> Self {
+        Self { source, sender }
+    }
+
+    pub fn source(&self) -> Source {
+        Source::File(self.source.clone())
+    }
+
+    /// Fails if updates aren't received anymore.
+    pub async fn send(&self, message: Update) -> Result<(), ()> {
+        self.sender
+            .send(SourceUpdate {
+                source: self.source(),
+                message,
+            })
+            .await
+            .map_err(|_| ())
+    }
+}
+
+/// Merges last update messages of every source, starting from the lowest priority. Among
+/// sources with the same priority, runtime ones win over config files, and among config files
+/// the one whose path sorts first wins. Sources without activities don't affect others.
+pub fn merge(sources: &BTreeMap<Source, SourceMessage>) -> UpdateMessage {
+    let mut sources = sources
+        .iter()
+        .filter(|(_, message)| !message.activities.is_empty())
+        .collect::<Vec<_>>();
+    let mut merged: BTreeMap<u64, UpdateMessageItem> = BTreeMap::new();
+
+    sources.sort_by(|(a_source, a), (b_source, b)| {
+        a.priority
+            .cmp(&b.priority)
+            .then_with(|| match (a_source, b_source) {
+                (Source::File(a_path), Source::File(b_path)) => b_path.cmp(a_path),
+                _ => a_source.cmp(b_source),
+            })
+    });
+
+    for (_, message) in sources {
+        if message.merge == MergeMode::Replace {
+            merged.clear();
+        }
+
+        for item in &message.activities {
+            match merged.get_mut(&item.application_id) {
+                Some(merged_item) if message.merge == MergeMode::Overlay => {
+                    merged_item.overlay(item)
+                }
+                _ => {
+                    merged.insert(item.application_id, item.clone());
+                }
+            }
+        }
+    }
+
+    merged.into_values().collect()
+}
+
+/// Returns config files in `dir`, skipping hidden and backup files which editors leave around.
+fn list_configs(dir: &Path) -> Option<BTreeSet<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            error!(
+                "Error while reading config directory `{}`: `{}`.",
+                dir.display(),
+                err
+            );
+
+            return None;
+        }
+    };
+
+    Some(
+        entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+                !name.starts_with('.') && !name.ends_with('~') && path.is_file()
+            })
+            .collect(),
+    )
+}
+
+/// Runs every file in a directory as an independent config, following files being added and
+/// removed.
+pub struct ConfigDir {
+    task: JoinHandle<()>,
+}
+
+impl ConfigDir {
+    async fn run(
+        dir: PathBuf,
+        updates_sender: mpsc::Sender<SourceUpdate>,
+        stdin_sender: broadcast::Sender<StdinMessage>,
+        restart_policy: RestartPolicy,
+        template_interval: Duration,
+        reload_sender: broadcast::Sender<()>,
+    ) {
+        let mut watcher = ConfigWatcher::new_dir(dir.clone());
+        let mut reload_receiver = reload_sender.subscribe();
+        let mut configs: BTreeMap<PathBuf, RichPresenceConfig> = BTreeMap::new();
+
+        loop {
+            if let Some(paths) = list_configs(&dir) {
+                let removed = configs
+                    .keys()
+                    .filter(|path| !paths.contains(*path))
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                for path in removed {
+                    info!("Config file `{}` was removed.", path.display());
+
+                    configs.remove(&path);
+
+                    let sender = SourceSender::new(path, updates_sender.clone());
+
+                    if sender
+                        .send(Update::Message(SourceMessage::default()))
+                        .await
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+
+                for path in paths {
+                    if configs.contains_key(&path) {
+                        continue;
+                    }
+
+                    info!("Loading config file `{}`.", path.display());
+
+                    let config = RichPresenceConfig::new(
+                        path.clone(),
+                        SourceSender::new(path.clone(), updates_sender.clone()),
+                        stdin_sender.clone(),
+                        restart_policy,
+                        template_interval,
+                        reload_sender.subscribe(),
+                    );
+
+                    configs.insert(path, config);
+                }
+            }
+
+            // Configs which are already loaded reload themselves, only the list of files is
+            // updated here.
+            select! {
+                () = watcher.changed() => {}
+                result = reload_receiver.recv() => {
+                    if let Err(broadcast::error::RecvError::Closed) = result {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn new(
+        dir: PathBuf,
+        updates_sender: mpsc::Sender<SourceUpdate>,
+        stdin_sender: broadcast::Sender<StdinMessage>,
+        restart_policy: RestartPolicy,
+        template_interval: Duration,
+        reload_sender: broadcast::Sender<()>,
+    ) -> Self {
+        Self {
+            task: tokio::spawn(Self::run(
+                dir,
+                updates_sender,
+                stdin_sender,
+                restart_policy,
+                template_interval,
+                reload_sender,
+            )),
+        }
+    }
+}
+
+impl Drop for ConfigDir {
+    fn drop(&mut self) {
+        self.task.abort()
+    }
+}
@@ -0,0 +1,213 @@
+/*
+    Copyright © 2021-2022 trickybestia <trickybestia@gmail.com>
+
+    This file is part of linux-discord-rich-presence.
+
+    linux-discord-rich-presence is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    linux-discord-rich-presence is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    collections::HashSet,
+    future::pending,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use lazy_static::lazy_static;
+use log::{debug, error, warn};
+use notify::{Config, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver},
+    time::{timeout_at, Instant},
+};
+
+lazy_static! {
+    /// Events which come closer to each other than this are treated as a single change.
+    static ref DEBOUNCE_DELAY: Duration = Duration::from_millis(200);
+    /// Change is reported after this long even if events keep coming.
+    static ref MAX_DEBOUNCE_DELAY: Duration = Duration::from_secs(2);
+}
+
+/// Returns paths whose changes affect the config: the config itself and the file it links to.
+fn watched_files(path: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+
+    if let (Some(parent), Some(name)) = (path.parent(), path.file_name()) {
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+
+        if let Ok(parent) = parent.canonicalize() {
+            files.push(parent.join(name));
+        }
+    }
+
+    if let Ok(target) = path.canonicalize() {
+        if !files.contains(&target) {
+            files.push(target);
+        }
+    }
+
+    files
+}
+
+/// Watches a config file through its directory, so changes are noticed even when editors
+/// replace the file instead of writing to it. Can also watch a directory of config files for
+/// files being added or removed.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    is_dir: bool,
+    watcher: Option<RecommendedWatcher>,
+    events: UnboundedReceiver<PathBuf>,
+    files: Vec<PathBuf>,
+    watched_dirs: HashSet<PathBuf>,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: PathBuf) -> Self {
+        Self::with_mode(path, false)
+    }
+
+    /// Watches files directly inside of `path` directory.
+    pub fn new_dir(path: PathBuf) -> Self {
+        Self::with_mode(path, true)
+    }
+
+    fn with_mode(path: PathBuf, is_dir: bool) -> Self {
+        let (tx, rx) = unbounded_channel();
+        let watcher = RecommendedWatcher::new(
+            move |event: notify::Result<notify::Event>| match event {
+                Ok(event) => {
+                    if let EventKind::Create(_)
+                    | EventKind::Modify(_)
+                    | EventKind::Remove(_)
+                    | EventKind::Access(_) = event.kind
+                    {
+                        for path in event.paths {
+                            // The receiver is gone only when the watcher is being dropped.
+                            let _ = tx.send(path);
+                        }
+                    }
+                }
+                Err(err) => warn!("Error while watching config file: `{}`.", err),
+            },
+            Config::default(),
+        );
+        let watcher = match watcher {
+            Ok(watcher) => Some(watcher),
+            Err(err) => {
+                error!(
+                    "Error while watching config file: `{}`. Config won't be reloaded on changes.",
+                    err
+                );
+
+                None
+            }
+        };
+        let mut config_watcher = Self {
+            path,
+            is_dir,
+            watcher,
+            events: rx,
+            files: Vec::new(),
+            watched_dirs: HashSet::new(),
+        };
+
+        config_watcher.rearm();
+
+        config_watcher
+    }
+
+    /// Watches directories of the config and of the file it links to, which may have changed.
+    fn rearm(&mut self) {
+        let watcher = match &mut self.watcher {
+            Some(watcher) => watcher,
+            None => return,
+        };
+
+        let dirs = if self.is_dir {
+            self.path.canonicalize().into_iter().collect()
+        } else {
+            self.files = watched_files(&self.path);
+
+            self.files
+                .iter()
+                .filter_map(|file| file.parent())
+                .map(Path::to_owned)
+                .collect::<Vec<_>>()
+        };
+
+        for dir in &dirs {
+            if self.watched_dirs.contains(dir) {
+                continue;
+            }
+
+            match watcher.watch(dir, RecursiveMode::NonRecursive) {
+                Ok(()) => {
+                    debug!("Watching `{}` for config changes.", dir.display());
+
+                    self.watched_dirs.insert(dir.clone());
+                }
+                Err(err) => warn!(
+                    "Error while watching `{}` for config changes: `{}`.",
+                    dir.display(),
+                    err
+                ),
+            }
+        }
+    }
+
+    fn is_relevant(&self, path: &Path) -> bool {
+        if self.is_dir {
+            path.parent()
+                .is_some_and(|dir| self.watched_dirs.contains(dir))
+        } else {
+            self.files.iter().any(|file| file == path)
+        }
+    }
+
+    /// Waits until the config changes (or, for a directory, any file in it). Bursts of events,
+    /// like the ones editors make while saving, are reported once.
+    pub async fn changed(&mut self) {
+        loop {
+            let path = match self.events.recv().await {
+                Some(path) => path,
+                None => pending().await,
+            };
+
+            if !self.is_relevant(&path) {
+                continue;
+            }
+
+            // Only relevant events extend the burst, so writes to unrelated files in the same
+            // directory can't postpone the change.
+            let deadline = Instant::now() + *MAX_DEBOUNCE_DELAY;
+            let mut quiet_until = Instant::now() + *DEBOUNCE_DELAY;
+
+            while let Ok(Some(path)) =
+                timeout_at(quiet_until.min(deadline), self.events.recv()).await
+            {
+                if self.is_relevant(&path) {
+                    quiet_until = Instant::now() + *DEBOUNCE_DELAY;
+                }
+            }
+
+            self.rearm();
+
+            return;
+        }
+    }
+}
@@ -0,0 +1,209 @@
+/*
+    Copyright © 2021-2022 trickybestia <trickybestia@gmail.com>
+
+    This file is part of linux-discord-rich-presence.
+
+    linux-discord-rich-presence is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    linux-discord-rich-presence is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    env::var_os,
+    fs::{self, Permissions},
+    io,
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::UnixStream as StdUnixStream,
+    },
+    path::PathBuf,
+};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    spawn,
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+
+use crate::{config_sources::Source, stdin_message::ConnectionStatus, update_message::Patch};
+
+const SOCKET_NAME: &str = "linux-discord-rich-presence.sock";
+
+/// Returns `$XDG_RUNTIME_DIR/linux-discord-rich-presence.sock`. Unlike Discord's socket, it
+/// isn't looked for in shared temporary directories, where other users could take its place.
+pub fn default_path() -> Option<PathBuf> {
+    var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| PathBuf::from(dir).join(SOCKET_NAME))
+}
+
+/// Command received through the control socket as a single JSON line.
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlCommand {
+    /// Replaces activities set through the control socket.
+    Set {
+        message: Value,
+    },
+    /// Changes a single activity set through the control socket.
+    Patch {
+        patch: Patch,
+    },
+    /// Removes activities set through the control socket.
+    Clear,
+    /// Clears presence in Discord until resumed, keeping activities.
+    Pause,
+    Resume,
+    Status,
+    /// Reloads config files, restarting Config Processes.
+    Reload,
+}
+
+/// Response to a command, written back as a single JSON line.
+#[derive(Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum ControlResponse {
+    Ok,
+    Error {
+        message: String,
+    },
+    Status {
+        paused: bool,
+        /// Config files and the control socket which currently set activities.
+        sources: Vec<Source>,
+        applications: Vec<ApplicationStatus>,
+    },
+}
+
+#[derive(Serialize)]
+pub struct ApplicationStatus {
+    pub application_id: u64,
+    pub state: ConnectionStatus,
+}
+
+pub struct ControlRequest {
+    /// Source under which activities are merged with the ones of configs.
+    pub source: Source,
+    pub command: ControlCommand,
+    pub reply: oneshot::Sender<ControlResponse>,
+}
+
+/// Listens for commands which change presence at runtime.
+pub struct ControlSocket {
+    path: PathBuf,
+    task: JoinHandle<()>,
+}
+
+impl ControlSocket {
+    async fn serve_connection(stream: UnixStream, requests_sender: mpsc::Sender<ControlRequest>) {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let response = match serde_json::from_str(&line) {
+                Ok(command) => {
+                    let (reply, response) = oneshot::channel();
+
+                    if requests_sender
+                        .send(ControlRequest {
+                            source: Source::ControlSocket,
+                            command,
+                            reply,
+                        })
+                        .await
+                        .is_err()
+                    {
+                        return;
+                    }
+
+                    match response.await {
+                        Ok(response) => response,
+                        Err(_) => return,
+                    }
+                }
+                Err(err) => ControlResponse::Error {
+                    message: format!("Error while parsing command: `{}`.", err),
+                },
+            };
+            let mut response = serde_json::to_string(&response).unwrap();
+
+            response.push('\n');
+
+            if writer.write_all(response.as_bytes()).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    async fn run(listener: UnixListener, requests_sender: mpsc::Sender<ControlRequest>) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    spawn(Self::serve_connection(stream, requests_sender.clone()));
+                }
+                Err(err) => {
+                    error!("Error while accepting control connection: `{}`.", err);
+
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Fails if another instance already listens on `path`.
+    pub fn new(path: PathBuf, requests_sender: mpsc::Sender<ControlRequest>) -> io::Result<Self> {
+        if StdUnixStream::connect(&path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another instance is listening on it",
+            ));
+        }
+
+        // Socket is left behind when the daemon is killed.
+        if let Ok(metadata) = fs::symlink_metadata(&path) {
+            if metadata.file_type().is_socket() {
+                fs::remove_file(&path)?;
+            }
+        }
+
+        let listener = UnixListener::bind(&path)?;
+
+        // Anyone who can connect can change presence and restart Config Processes.
+        if let Err(err) = fs::set_permissions(&path, Permissions::from_mode(0o600)) {
+            let _ = fs::remove_file(&path);
+
+            return Err(err);
+        }
+
+        Ok(Self {
+            path,
+            task: spawn(Self::run(listener, requests_sender)),
+        })
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        self.task.abort();
+
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,153 @@
+/*
+    Copyright © 2021-2022 trickybestia <trickybestia@gmail.com>
+
+    This file is part of linux-discord-rich-presence.
+
+    linux-discord-rich-presence is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    linux-discord-rich-presence is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+};
+
+use clap::Subcommand;
+use serde_json::{json, Value};
+
+use crate::control_socket;
+
+/// Changes presence of the running instance through its control socket
+#[derive(clap::Args)]
+pub struct CtlArgs {
+    /// Path to the control socket [default: $XDG_RUNTIME_DIR/linux-discord-rich-presence.sock]
+    #[clap(long)]
+    socket: Option<PathBuf>,
+    #[clap(subcommand)]
+    command: CtlCommand,
+}
+
+#[derive(Subcommand)]
+enum CtlCommand {
+    /// Replaces activities set through the control socket
+    Set {
+        /// Update message in JSON, or `-` to read it from stdin
+        message: String,
+    },
+    /// Changes a single activity set through the control socket
+    Patch {
+        /// Patch in JSON, like `{"op": "patch", "application_id": 0, "state": "Idle"}`
+        patch: String,
+    },
+    /// Removes activities set through the control socket
+    Clear,
+    /// Clears presence in Discord until resumed
+    Pause,
+    /// Shows activities again after pause
+    Resume,
+    /// Prints state of applications as JSON
+    Status,
+    /// Reloads config files, restarting Config Processes
+    Reload,
+}
+
+fn parse_json(value: &str) -> Result<Value, String> {
+    let value = if value == "-" {
+        let mut input = String::new();
+
+        io::stdin()
+            .read_to_string(&mut input)
+            .map_err(|err| format!("Error while reading stdin: `{}`.", err))?;
+
+        input
+    } else {
+        value.to_owned()
+    };
+
+    serde_json::from_str(&value).map_err(|err| format!("Error while parsing JSON: `{}`.", err))
+}
+
+fn request(socket: &Path, command: &Value) -> io::Result<Value> {
+    let mut stream = UnixStream::connect(socket)?;
+
+    stream.write_all(format!("{}\n", command).as_bytes())?;
+
+    let mut response = String::new();
+
+    BufReader::new(stream).read_line(&mut response)?;
+
+    serde_json::from_str(&response).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Sends the command to the running instance. Returns `false` if it failed.
+pub fn ctl(args: CtlArgs) -> bool {
+    let Some(socket) = args.socket.or_else(control_socket::default_path) else {
+        eprintln!(
+            "Couldn't find control socket: `XDG_RUNTIME_DIR` isn't set, use `--socket` to set its path."
+        );
+
+        return false;
+    };
+    let command = match args.command {
+        CtlCommand::Set { message } => match parse_json(&message) {
+            Ok(message) => json!({ "command": "set", "message": message }),
+            Err(err) => {
+                eprintln!("{}", err);
+
+                return false;
+            }
+        },
+        CtlCommand::Patch { patch } => match parse_json(&patch) {
+            Ok(patch) => json!({ "command": "patch", "patch": patch }),
+            Err(err) => {
+                eprintln!("{}", err);
+
+                return false;
+            }
+        },
+        CtlCommand::Clear => json!({ "command": "clear" }),
+        CtlCommand::Pause => json!({ "command": "pause" }),
+        CtlCommand::Resume => json!({ "command": "resume" }),
+        CtlCommand::Status => json!({ "command": "status" }),
+        CtlCommand::Reload => json!({ "command": "reload" }),
+    };
+    let mut response = match request(&socket, &command) {
+        Ok(response) => response,
+        Err(err) => {
+            eprintln!(
+                "Error while sending command to `{}`: `{}`.",
+                socket.display(),
+                err
+            );
+
+            return false;
+        }
+    };
+
+    match response["result"].as_str() {
+        Some("ok") => true,
+        Some("status") => {
+            response.as_object_mut().unwrap().remove("result");
+
+            println!("{}", serde_json::to_string_pretty(&response).unwrap());
+
+            true
+        }
+        _ => {
+            eprintln!("{}", response["message"].as_str().unwrap_or_default());
+
+            false
+        }
+    }
+}
@@ -0,0 +1,217 @@
+/*
+    Copyright © 2021-2022 trickybestia <trickybestia@gmail.com>
+
+    This file is part of linux-discord-rich-presence.
+
+    linux-discord-rich-presence is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    linux-discord-rich-presence is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use log::error;
+use tokio::{
+    spawn,
+    sync::{mpsc, oneshot, watch},
+    task::JoinHandle,
+};
+use zbus::{
+    connection,
+    fdo::{self, RequestNameFlags},
+    interface, Connection,
+};
+
+use crate::{
+    config_sources::Source,
+    control_socket::{ControlCommand, ControlRequest, ControlResponse},
+    update_message::UpdateMessage,
+};
+
+const BUS_NAME: &str = "org.linuxdiscordrichpresence.Daemon";
+const OBJECT_PATH: &str = "/org/linuxdiscordrichpresence/Daemon";
+
+/// State of presence which is exposed as D-Bus properties.
+#[derive(Clone, Default, PartialEq)]
+pub struct PresenceState {
+    pub paused: bool,
+    /// Activities after merging and sanitization, including paused ones.
+    pub activities: UpdateMessage,
+    /// Applications which are connected to Discord.
+    pub connected: Vec<u64>,
+    pub last_error: String,
+}
+
+struct Daemon {
+    requests_sender: mpsc::Sender<ControlRequest>,
+    state: watch::Receiver<PresenceState>,
+}
+
+impl Daemon {
+    async fn request(&self, command: ControlCommand) -> fdo::Result<()> {
+        let (reply, response) = oneshot::channel();
+
+        self.requests_sender
+            .send(ControlRequest {
+                source: Source::Dbus,
+                command,
+                reply,
+            })
+            .await
+            .map_err(|_| fdo::Error::Failed("Daemon is shutting down.".to_owned()))?;
+
+        match response.await {
+            Ok(ControlResponse::Error { message }) => Err(fdo::Error::InvalidArgs(message)),
+            Ok(_) => Ok(()),
+            Err(_) => Err(fdo::Error::Failed("Daemon is shutting down.".to_owned())),
+        }
+    }
+}
+
+#[interface(name = "org.linuxdiscordrichpresence.Daemon")]
+impl Daemon {
+    /// Sets activities, given as update message in JSON. They are merged with the ones of
+    /// configs.
+    async fn set_activity(&self, message: &str) -> fdo::Result<()> {
+        let message = serde_json::from_str(message).map_err(|err| {
+            fdo::Error::InvalidArgs(format!("Error while parsing JSON: `{}`.", err))
+        })?;
+
+        self.request(ControlCommand::Set { message }).await
+    }
+
+    /// Removes activities set through D-Bus.
+    async fn clear(&self) -> fdo::Result<()> {
+        self.request(ControlCommand::Clear).await
+    }
+
+    async fn pause(&self) -> fdo::Result<()> {
+        self.request(ControlCommand::Pause).await
+    }
+
+    async fn resume(&self) -> fdo::Result<()> {
+        self.request(ControlCommand::Resume).await
+    }
+
+    async fn reload(&self) -> fdo::Result<()> {
+        self.request(ControlCommand::Reload).await
+    }
+
+    #[zbus(property)]
+    async fn paused(&self) -> bool {
+        self.state.borrow().paused
+    }
+
+    #[zbus(property)]
+    async fn connected(&self) -> Vec<u64> {
+        self.state.borrow().connected.clone()
+    }
+
+    /// Activities in JSON, in the same format as update messages.
+    #[zbus(property)]
+    async fn current_activities(&self) -> String {
+        serde_json::to_string(&self.state.borrow().activities).unwrap()
+    }
+
+    #[zbus(property)]
+    async fn last_error(&self) -> String {
+        self.state.borrow().last_error.clone()
+    }
+}
+
+/// Owns `org.linuxdiscordrichpresence.Daemon` name on the session bus.
+pub struct DbusService {
+    _connection: Connection,
+    task: JoinHandle<()>,
+}
+
+impl DbusService {
+    /// Emits `PropertiesChanged` signals when the state changes.
+    async fn publish(connection: Connection, mut state: watch::Receiver<PresenceState>) {
+        let daemon = match connection
+            .object_server()
+            .interface::<_, Daemon>(OBJECT_PATH)
+            .await
+        {
+            Ok(daemon) => daemon,
+            Err(err) => {
+                error!("Error while publishing D-Bus properties: `{}`.", err);
+
+                return;
+            }
+        };
+        let mut last_state = state.borrow().clone();
+
+        while state.changed().await.is_ok() {
+            let new_state = state.borrow_and_update().clone();
+            let emitter = daemon.signal_emitter();
+            let iface = daemon.get().await;
+
+            let result = async {
+                if new_state.paused != last_state.paused {
+                    iface.paused_changed(emitter).await?;
+                }
+
+                if new_state.connected != last_state.connected {
+                    iface.connected_changed(emitter).await?;
+                }
+
+                if new_state.activities != last_state.activities {
+                    iface.current_activities_changed(emitter).await?;
+                }
+
+                if new_state.last_error != last_state.last_error {
+                    iface.last_error_changed(emitter).await?;
+                }
+
+                zbus::Result::Ok(())
+            }
+            .await;
+
+            if let Err(err) = result {
+                error!("Error while emitting D-Bus signal: `{}`.", err);
+            }
+
+            last_state = new_state;
+        }
+    }
+
+    /// Fails if there is no session bus or the name is owned by another instance.
+    pub async fn new(
+        requests_sender: mpsc::Sender<ControlRequest>,
+        state: watch::Receiver<PresenceState>,
+    ) -> zbus::Result<Self> {
+        let daemon = Daemon {
+            requests_sender,
+            state: state.clone(),
+        };
+        let connection = connection::Builder::session()?
+            .serve_at(OBJECT_PATH, daemon)?
+            .build()
+            .await?;
+
+        // By default, the name is queued until its owner exits, so another instance would keep
+        // running without it.
+        connection
+            .request_name_with_flags(BUS_NAME, RequestNameFlags::DoNotQueue.into())
+            .await?;
+
+        Ok(Self {
+            task: spawn(Self::publish(connection.clone(), state)),
+            _connection: connection,
+        })
+    }
+}
+
+impl Drop for DbusService {
+    fn drop(&mut self) {
+        self.task.abort()
+    }
+}
@@ -0,0 +1,91 @@
+/*
+    Copyright © 2021-2022 trickybestia <trickybestia@gmail.com>
+
+    This file is part of linux-discord-rich-presence.
+
+    linux-discord-rich-presence is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    linux-discord-rich-presence is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use log::{debug, warn};
+use notify::{Config, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc::Sender;
+
+use crate::ipc_socket::{runtime_dir, APP_SUBPATHS};
+
+/// Notifies when a Discord IPC socket is created, so disconnected clients can reconnect at once.
+pub struct DiscordSocketWatcher {
+    _watcher: Option<RecommendedWatcher>,
+}
+
+impl DiscordSocketWatcher {
+    fn watch(sender: Sender<()>) -> notify::Result<Option<RecommendedWatcher>> {
+        let runtime_dir = match runtime_dir() {
+            Some(runtime_dir) => runtime_dir,
+            None => return Ok(None),
+        };
+        let mut watcher = RecommendedWatcher::new(
+            move |event: notify::Result<notify::Event>| {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(err) => {
+                        warn!("Error while watching Discord IPC socket: `{}`.", err);
+
+                        return;
+                    }
+                };
+
+                if !matches!(event.kind, EventKind::Create(_)) {
+                    return;
+                }
+
+                let is_socket = event.paths.iter().any(|path| {
+                    path.file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| name.starts_with("discord-ipc-"))
+                });
+
+                if is_socket {
+                    // A full channel already has a pending notification.
+                    let _ = sender.try_send(());
+                }
+            },
+            Config::default(),
+        )?;
+
+        for subpath in APP_SUBPATHS {
+            let path = runtime_dir.join(subpath);
+
+            if path.is_dir() {
+                watcher.watch(&path, RecursiveMode::NonRecursive)?;
+
+                debug!("Watching `{}` for Discord IPC socket.", path.display());
+            }
+        }
+
+        Ok(Some(watcher))
+    }
+
+    pub fn new(sender: Sender<()>) -> Self {
+        let watcher = Self::watch(sender).unwrap_or_else(|err| {
+            warn!(
+                "Error while watching Discord IPC socket: `{}`. Reconnection will rely on retries only.",
+                err
+            );
+
+            None
+        });
+
+        Self { _watcher: watcher }
+    }
+}
@@ -0,0 +1,211 @@
+/*
+    Copyright © 2021-2022 trickybestia <trickybestia@gmail.com>
+
+    This file is part of linux-discord-rich-presence.
+
+    linux-discord-rich-presence is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    linux-discord-rich-presence is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::{env::var, error::Error, io, path::PathBuf, time::Duration};
+
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        unix::{OwnedReadHalf, OwnedWriteHalf},
+        UnixStream,
+    },
+    spawn,
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+    time::timeout,
+};
+
+use crate::stdin_message::StdinMessage;
+
+// Same lookup order as `discord-rich-presence` uses to find the IPC socket.
+const ENV_KEYS: [&str; 4] = ["XDG_RUNTIME_DIR", "TMPDIR", "TMP", "TEMP"];
+pub const APP_SUBPATHS: [&str; 4] = [
+    "",
+    "app/com.discordapp.Discord/",
+    "snap.discord-canary/",
+    "snap.discord/",
+];
+
+/// Events which are forwarded to Config Process instead of being treated as responses.
+pub const FORWARDED_EVENTS: [&str; 3] = [
+    "ACTIVITY_JOIN",
+    "ACTIVITY_SPECTATE",
+    "ACTIVITY_JOIN_REQUEST",
+];
+
+pub const OPCODE_HANDSHAKE: u32 = 0;
+pub const OPCODE_FRAME: u32 = 1;
+pub const OPCODE_CLOSE: u32 = 2;
+pub const OPCODE_PING: u32 = 3;
+pub const OPCODE_PONG: u32 = 4;
+
+lazy_static! {
+    static ref RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+}
+
+pub fn runtime_dir() -> Option<PathBuf> {
+    ENV_KEYS
+        .iter()
+        .find_map(|key| var(key).ok())
+        .map(PathBuf::from)
+}
+
+async fn read_frame(stream: &mut OwnedReadHalf) -> io::Result<(u32, Value)> {
+    let mut header = [0; 8];
+
+    stream.read_exact(&mut header).await?;
+
+    let opcode = u32::from_le_bytes(header[..4].try_into().unwrap());
+    let length = u32::from_le_bytes(header[4..].try_into().unwrap());
+    let mut data = vec![0; length as usize];
+
+    stream.read_exact(&mut data).await?;
+
+    serde_json::from_slice(&data)
+        .map(|payload| (opcode, payload))
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn encode_frame(payload: &Value, opcode: u32) -> Vec<u8> {
+    let data = payload.to_string();
+    let mut frame = Vec::with_capacity(8 + data.len());
+
+    frame.extend_from_slice(&opcode.to_le_bytes());
+    frame.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    frame.extend_from_slice(data.as_bytes());
+
+    frame
+}
+
+/// Reads frames until the socket is closed, forwarding subscribed events to Config Process
+/// and everything else to [`IpcSocket::recv`].
+async fn read_frames(
+    mut stream: OwnedReadHalf,
+    application_id: u64,
+    frames: mpsc::UnboundedSender<(u32, Value)>,
+    stdin_sender: broadcast::Sender<StdinMessage>,
+) {
+    while let Ok((opcode, payload)) = read_frame(&mut stream).await {
+        let event = payload["evt"].as_str().unwrap_or_default();
+
+        if opcode == OPCODE_FRAME
+            && payload["cmd"] == "DISPATCH"
+            && FORWARDED_EVENTS.contains(&event)
+        {
+            // There may be no Config Process to receive it, which is fine.
+            let _ = stdin_sender.send(StdinMessage::DiscordEvent {
+                application_id,
+                event: event.to_owned(),
+                data: payload["data"].clone(),
+            });
+
+            continue;
+        }
+
+        if frames.send((opcode, payload)).is_err() {
+            break;
+        }
+    }
+}
+
+/// Discord IPC transport which reads the socket in a background task, so events sent by
+/// Discord are delivered as soon as they arrive.
+pub struct IpcSocket {
+    application_id: u64,
+    stream: Option<OwnedWriteHalf>,
+    frames: Option<mpsc::UnboundedReceiver<(u32, Value)>>,
+    reader_task: Option<JoinHandle<()>>,
+    stdin_sender: broadcast::Sender<StdinMessage>,
+}
+
+impl IpcSocket {
+    pub fn new(application_id: u64, stdin_sender: broadcast::Sender<StdinMessage>) -> Self {
+        Self {
+            application_id,
+            stream: None,
+            frames: None,
+            reader_task: None,
+            stdin_sender,
+        }
+    }
+
+    pub async fn connect(&mut self) -> Result<(), Box<dyn Error>> {
+        let runtime_dir = runtime_dir().unwrap_or_default();
+
+        for i in 0..10 {
+            for subpath in APP_SUBPATHS {
+                let path = runtime_dir.join(subpath).join(format!("discord-ipc-{}", i));
+
+                if let Ok(stream) = UnixStream::connect(&path).await {
+                    let (reader, writer) = stream.into_split();
+                    let (tx, rx) = mpsc::unbounded_channel();
+
+                    self.reader_task = Some(spawn(read_frames(
+                        reader,
+                        self.application_id,
+                        tx,
+                        self.stdin_sender.clone(),
+                    )));
+                    self.stream = Some(writer);
+                    self.frames = Some(rx);
+
+                    return Ok(());
+                }
+            }
+        }
+
+        Err("Couldn't connect to the Discord IPC socket".into())
+    }
+
+    pub async fn send(&mut self, payload: Value, opcode: u32) -> Result<(), Box<dyn Error>> {
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or("Couldn't retrieve the Discord IPC socket")?;
+
+        stream.write_all(&encode_frame(&payload, opcode)).await?;
+
+        Ok(())
+    }
+
+    pub async fn recv(&mut self) -> Result<(u32, Value), Box<dyn Error>> {
+        let frames = self.frames.as_mut().ok_or("Not connected to Discord")?;
+
+        match timeout(*RESPONSE_TIMEOUT, frames.recv()).await {
+            Ok(Some(frame)) => Ok(frame),
+            Ok(None) => Err("Discord IPC socket was closed".into()),
+            Err(_) => Err("Discord didn't respond in time".into()),
+        }
+    }
+}
+
+impl Drop for IpcSocket {
+    fn drop(&mut self) {
+        if let Some(stream) = &self.stream {
+            // Closing without waiting: the socket is shut down right after anyway.
+            let _ = stream.try_write(&encode_frame(&json!({}), OPCODE_CLOSE));
+        }
+
+        if let Some(reader_task) = &self.reader_task {
+            reader_task.abort();
+        }
+    }
+}
@@ -17,65 +17,365 @@
     along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
 */
 
+mod backoff;
+mod check;
+mod config_sources;
+mod config_watcher;
+mod control_socket;
+mod ctl;
+mod dbus_service;
+mod discord_socket_watcher;
+mod ipc_socket;
 mod process_wrapper;
 mod rich_presence_client;
 mod rich_presence_config;
 mod rich_presence_controller;
+mod sanitization;
+mod stdin_message;
+mod template;
+mod timestamp;
 mod update_message;
+mod validation;
 
-use std::{path::PathBuf, time::Duration};
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    process,
+    time::{Duration, Instant},
+};
 
-use clap::Parser;
+use check::CheckArgs;
+use clap::{Parser, Subcommand};
+use ctl::CtlArgs;
 use lazy_static::lazy_static;
-use log::{info, warn};
-use rich_presence_config::RichPresenceConfig;
+use log::{error, info, warn};
+use rich_presence_config::{RestartMode, RestartPolicy, RichPresenceConfig};
+use sanitization::SanitizeMode;
 use simplelog::{ColorChoice, ConfigBuilder, LevelFilter, TermLogger, TerminalMode};
 use tokio::{
-    sync::mpsc::{channel, Receiver},
-    time::timeout,
+    select,
+    sync::{
+        broadcast,
+        mpsc::{channel, Receiver},
+        watch,
+    },
+    time::sleep_until,
 };
 
-use crate::{rich_presence_controller::RichPresenceController, update_message::UpdateMessage};
+use crate::{
+    backoff::{parse_seconds, Backoff},
+    config_sources::{ConfigDir, Source, SourceSender, SourceUpdate},
+    control_socket::{
+        ApplicationStatus, ControlCommand, ControlRequest, ControlResponse, ControlSocket,
+    },
+    dbus_service::{DbusService, PresenceState},
+    discord_socket_watcher::DiscordSocketWatcher,
+    rich_presence_controller::RichPresenceController,
+    stdin_message::StdinMessage,
+    update_message::{ParseError, SourceMessage, Update, UpdateMessage},
+};
 
 lazy_static! {
-    static ref UPDATE_DELAY: Duration = Duration::from_secs(10);
+    static ref HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+    /// Time given to Discord to start accepting connections after its socket appears.
+    static ref SOCKET_SETTLE_DELAY: Duration = Duration::from_millis(500);
 }
 
-async fn process_rich_presence(mut updates_receiver: Receiver<UpdateMessage>) {
-    let mut controller = RichPresenceController::new();
-    let mut is_connected = false;
+/// Stores `update` as the last message of `source`, or applies it to that message if it's a
+/// patch.
+fn apply_update(
+    sources: &mut BTreeMap<Source, SourceMessage>,
+    source: Source,
+    update: Update,
+) -> Result<(), ParseError> {
+    match update {
+        Update::Message(message) => {
+            sources.insert(source, message);
+
+            Ok(())
+        }
+        Update::Patch(patch) => sources.entry(source).or_default().apply(patch),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn process_rich_presence(
+    mut updates_receiver: Receiver<SourceUpdate>,
+    mut sockets_receiver: Receiver<()>,
+    mut control_receiver: Receiver<ControlRequest>,
+    stdin_sender: broadcast::Sender<StdinMessage>,
+    reload_sender: broadcast::Sender<()>,
+    state_sender: watch::Sender<PresenceState>,
+    backoff: Backoff,
+    sanitize_mode: SanitizeMode,
+) {
+    let mut controller =
+        RichPresenceController::new(backoff, *HEARTBEAT_INTERVAL, stdin_sender.clone());
+    let mut sources: BTreeMap<_, SourceMessage> = BTreeMap::new();
     let mut last_message = UpdateMessage::new();
+    let mut rejected = Vec::new();
+    let mut is_paused = false;
+    let paused_message = UpdateMessage::new();
+    let mut last_error = String::new();
 
     loop {
-        let message = timeout(*UPDATE_DELAY, updates_receiver.recv()).await;
+        let deadline = controller
+            .next_deadline()
+            .unwrap_or_else(|| Instant::now() + *HEARTBEAT_INTERVAL);
+        let mut is_new_message = false;
+        // Config file whose update message is acknowledged, if the update came from one.
+        let mut ack_source = None;
 
-        if let Ok(Some(message)) = message {
-            last_message = message;
-        }
+        select! {
+            Some(update) = updates_receiver.recv() => {
+                let application_id = match &update.message {
+                    Update::Patch(patch) => Some(patch.application_id()),
+                    Update::Message(_) => None,
+                };
 
-        match controller.update(&last_message).await {
-            Ok(()) => {
-                if !is_connected {
-                    is_connected = true;
+                match apply_update(&mut sources, update.source.clone(), update.message) {
+                    Ok(()) => {
+                        is_new_message = true;
+                        ack_source = Some(update.source);
+                    }
+                    Err(err) => {
+                        error!("Error while applying patch: `{}`.", err);
 
-                    info!("Connected to Discord!");
+                        // Activities are unchanged, so only the patched application failed.
+                        let _ = stdin_sender.send(StdinMessage::Ack {
+                            applied: Vec::new(),
+                            failed: application_id.into_iter().collect(),
+                            source: update.source,
+                        });
+                    }
                 }
             }
-            Err(err) => {
-                is_connected = false;
+            Some(request) = control_receiver.recv() => {
+                let response = match request.command {
+                    // `from_value` would blame Config Process, which isn't involved here.
+                    ControlCommand::Set { message } if message.get("op").is_some() => {
+                        ControlResponse::Error {
+                            message: match request.source {
+                                Source::Dbus => "D-Bus accepts only whole update messages.",
+                                _ => "Patches can't be set. Use `patch` command instead.",
+                            }
+                            .to_owned(),
+                        }
+                    }
+                    ControlCommand::Set { message } => match update_message::from_value(message) {
+                        Ok(message) => {
+                            sources.insert(request.source, message);
+                            is_new_message = true;
+
+                            ControlResponse::Ok
+                        }
+                        Err(err) => ControlResponse::Error {
+                            message: format!("Error while parsing update message: `{}`.", err),
+                        },
+                    },
+                    ControlCommand::Patch { patch } => {
+                        match apply_update(&mut sources, request.source, Update::Patch(patch)) {
+                            Ok(()) => {
+                                is_new_message = true;
+
+                                ControlResponse::Ok
+                            }
+                            Err(err) => ControlResponse::Error {
+                                message: format!("Error while applying patch: `{}`.", err),
+                            },
+                        }
+                    }
+                    ControlCommand::Clear => {
+                        sources.remove(&request.source);
+                        is_new_message = true;
+
+                        ControlResponse::Ok
+                    }
+                    ControlCommand::Pause => {
+                        info!("Presence was paused.");
+
+                        is_paused = true;
+
+                        ControlResponse::Ok
+                    }
+                    ControlCommand::Resume => {
+                        info!("Presence was resumed.");
+
+                        is_paused = false;
+
+                        ControlResponse::Ok
+                    }
+                    ControlCommand::Status => ControlResponse::Status {
+                        paused: is_paused,
+                        sources: sources
+                            .iter()
+                            .filter(|(_, message)| !message.activities.is_empty())
+                            .map(|(source, _)| source.clone())
+                            .collect(),
+                        applications: last_message
+                            .iter()
+                            .map(|item| ApplicationStatus {
+                                application_id: item.application_id,
+                                state: controller.connection_status(item.application_id),
+                            })
+                            .collect(),
+                    },
+                    ControlCommand::Reload => {
+                        // There may be no config which would reload, e.g. an empty config
+                        // directory.
+                        let _ = reload_sender.send(());
+
+                        ControlResponse::Ok
+                    }
+                };
 
-                warn!("{} Retrying after {} seconds.", err, UPDATE_DELAY.as_secs());
+                // The client may have disconnected already.
+                let _ = request.reply.send(response);
+            }
+            Some(()) = sockets_receiver.recv() => {
+                info!("Discord IPC socket appeared! Reconnecting...");
+
+                controller.retry_at(Instant::now() + *SOCKET_SETTLE_DELAY);
+            }
+            () = sleep_until(deadline.into()) => {}
+        }
+
+        if is_new_message {
+            (last_message, rejected) =
+                sanitization::apply(sanitize_mode, config_sources::merge(&sources));
+        }
+
+        let shown_message = if is_paused {
+            &paused_message
+        } else {
+            &last_message
+        };
+        let report = controller.update(shown_message).await;
+
+        // Nobody may listen to these messages, so send errors are ignored.
+        for (application_id, result) in report {
+            if let Err(err) = result {
+                last_error = format!("Application {}: {}", application_id, err);
+
+                let _ = stdin_sender.send(StdinMessage::Error {
+                    application_id,
+                    message: err.to_string(),
+                });
             }
         }
+
+        if is_new_message {
+            let (applied, mut failed): (Vec<_>, Vec<_>) = shown_message
+                .iter()
+                .map(|item| item.application_id)
+                .partition(|application_id| controller.is_connected(*application_id));
+
+            for (application_id, message) in &rejected {
+                error!("Application {}: {}", application_id, message);
+
+                last_error = format!("Application {}: {}", application_id, message);
+
+                let _ = stdin_sender.send(StdinMessage::Error {
+                    application_id: *application_id,
+                    message: message.clone(),
+                });
+
+                failed.push(*application_id);
+            }
+
+            if let Some(source) = ack_source {
+                let _ = stdin_sender.send(StdinMessage::Ack {
+                    applied,
+                    failed,
+                    source,
+                });
+            }
+        }
+
+        let state = PresenceState {
+            paused: is_paused,
+            activities: last_message.clone(),
+            connected: shown_message
+                .iter()
+                .map(|item| item.application_id)
+                .filter(|application_id| controller.is_connected(*application_id))
+                .collect(),
+            last_error: last_error.clone(),
+        };
+
+        // Observers are notified only about changes.
+        state_sender.send_if_modified(|current| {
+            if *current == state {
+                return false;
+            }
+
+            *current = state;
+
+            true
+        });
     }
 }
 
+#[derive(Subcommand)]
+enum Command {
+    Check(CheckArgs),
+    Ctl(CtlArgs),
+    /// Prints JSON Schema of update messages
+    Schema,
+}
+
 #[derive(Parser)]
-#[clap(author, version, about)]
+#[clap(author, version, about, subcommand_negates_reqs = true)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
     /// Path to the config file
-    #[clap(short, long)]
-    config: PathBuf,
+    #[clap(
+        short,
+        long,
+        required_unless_present = "config-dir",
+        conflicts_with = "config-dir"
+    )]
+    config: Option<PathBuf>,
+    /// Path to a directory whose every file is a separate config
+    #[clap(long)]
+    config_dir: Option<PathBuf>,
+    /// Delay before the first reconnection attempt, in seconds
+    #[clap(long, default_value = "1", value_name = "SECONDS", value_parser = parse_seconds)]
+    backoff_initial: Duration,
+    /// Maximum delay between reconnection attempts, in seconds
+    #[clap(long, default_value = "60", value_name = "SECONDS", value_parser = parse_seconds)]
+    backoff_max: Duration,
+    /// Factor by which the delay grows after every failed attempt
+    #[clap(long, default_value_t = 2.0, value_parser = backoff::parse_multiplier)]
+    backoff_multiplier: f64,
+    /// Fraction of the delay which is randomly added to or subtracted from it
+    #[clap(long, default_value_t = 0.1, value_parser = backoff::parse_jitter)]
+    backoff_jitter: f64,
+    /// When to restart Config Process after it exits
+    #[clap(long, value_enum, default_value_t = RestartMode::Never)]
+    restart: RestartMode,
+    /// Count of consecutive restarts after which Config Process is considered permanently failed
+    #[clap(long, default_value_t = 5)]
+    restart_max: u32,
+    /// Delay before the first restart of Config Process, in seconds. Next delays grow like reconnection ones
+    #[clap(long, default_value = "1", value_name = "SECONDS", value_parser = parse_seconds)]
+    restart_delay: Duration,
+    /// Clear activity when Config Process is considered permanently failed
+    #[clap(long)]
+    clear_on_failure: bool,
+    /// What to do with activities which violate Discord's limits
+    #[clap(long, value_enum, default_value_t = SanitizeMode::Lenient)]
+    sanitize: SanitizeMode,
+    /// Interval between expansions of templates in static configs, in seconds
+    #[clap(long, default_value = "15", value_name = "SECONDS", value_parser = parse_seconds)]
+    template_interval: Duration,
+    /// Path to the control socket [default: $XDG_RUNTIME_DIR/linux-discord-rich-presence.sock]
+    #[clap(long)]
+    control_socket: Option<PathBuf>,
+    /// Don't own org.linuxdiscordrichpresence.Daemon name on D-Bus session bus
+    #[clap(long)]
+    no_dbus: bool,
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -91,9 +391,121 @@ async fn main() {
     )
     .unwrap();
 
+    timestamp::init();
+
     let args = Args::parse();
+
+    match args.command {
+        Some(Command::Check(check_args)) => {
+            process::exit(if check::check(check_args).await { 0 } else { 1 });
+        }
+        Some(Command::Ctl(ctl_args)) => {
+            process::exit(if ctl::ctl(ctl_args) { 0 } else { 1 });
+        }
+        Some(Command::Schema) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&update_message::schema()).unwrap()
+            );
+
+            return;
+        }
+        None => {}
+    }
+
+    let backoff = Backoff {
+        initial: args.backoff_initial,
+        max: args.backoff_max,
+        multiplier: args.backoff_multiplier,
+        jitter: args.backoff_jitter,
+    };
+    let restart_policy = RestartPolicy {
+        mode: args.restart,
+        max_restarts: args.restart_max,
+        backoff: Backoff {
+            initial: args.restart_delay,
+            max: args.backoff_max,
+            ..backoff
+        },
+        clear_on_failure: args.clear_on_failure,
+    };
     let (tx, rx) = channel(10);
-    let _config = RichPresenceConfig::new(args.config, tx);
+    let (sockets_tx, sockets_rx) = channel(1);
+    let (control_tx, control_rx) = channel(10);
+    let (stdin_tx, _) = broadcast::channel(16);
+    let (reload_tx, _) = broadcast::channel(1);
+    let (state_tx, state_rx) = watch::channel(PresenceState::default());
+    let mut _config = None;
+    let mut _config_dir = None;
+
+    if let Some(config) = args.config {
+        let sender = SourceSender::new(config.clone(), tx);
+
+        _config = Some(RichPresenceConfig::new(
+            config,
+            sender,
+            stdin_tx.clone(),
+            restart_policy,
+            args.template_interval,
+            reload_tx.subscribe(),
+        ));
+    } else if let Some(config_dir) = args.config_dir {
+        _config_dir = Some(ConfigDir::new(
+            config_dir,
+            tx,
+            stdin_tx.clone(),
+            restart_policy,
+            args.template_interval,
+            reload_tx.clone(),
+        ));
+    }
+
+    let _dbus_service = if args.no_dbus {
+        None
+    } else {
+        match DbusService::new(control_tx.clone(), state_rx).await {
+            Ok(dbus_service) => Some(dbus_service),
+            Err(err) => {
+                error!("Error while connecting to D-Bus session bus: `{}`.", err);
+
+                None
+            }
+        }
+    };
+    let _control_socket = match args.control_socket.or_else(control_socket::default_path) {
+        Some(path) => match ControlSocket::new(path.clone(), control_tx) {
+            Ok(control_socket) => Some(control_socket),
+            Err(err) => {
+                error!(
+                    "Error while creating control socket `{}`: `{}`.",
+                    path.display(),
+                    err
+                );
+
+                None
+            }
+        },
+        None => {
+            warn!(
+                "Control socket is disabled: `XDG_RUNTIME_DIR` isn't set, use \
+                `--control-socket` to set its path."
+            );
 
-    process_rich_presence(rx).await;
+            None
+        }
+    };
+
+    let _socket_watcher = DiscordSocketWatcher::new(sockets_tx);
+
+    process_rich_presence(
+        rx,
+        sockets_rx,
+        control_rx,
+        stdin_tx,
+        reload_tx,
+        state_tx,
+        backoff,
+        args.sanitize,
+    )
+    .await;
 }
@@ -17,36 +17,217 @@
     along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use std::{ffi::OsStr, process::Stdio};
+use std::{
+    collections::VecDeque,
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
+use lazy_static::lazy_static;
+use log::warn;
 use tokio::{
-    io::{self, AsyncBufReadExt, BufReader, Lines},
-    process::{Child, ChildStdout, Command},
+    io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::{Child, ChildStderr, ChildStdin, ChildStdout, Command},
+    spawn,
+    sync::mpsc::{self, error::TrySendError},
+    task::JoinHandle,
+    time::timeout,
 };
 
+/// Count of lines which can wait to be written to the process' stdin. Further lines are
+/// dropped, so a process which never reads its stdin can't block us.
+const STDIN_QUEUE_SIZE: usize = 64;
+/// Count of last stderr lines which are kept for error reports.
+const STDERR_HISTORY_SIZE: usize = 20;
+
+lazy_static! {
+    /// How long to wait for the rest of stderr after the process closed its stdout.
+    static ref STDERR_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SpawnError {
+    #[error("Interpreter `{interpreter}` from the shebang of `{path}` wasn't found.")]
+    MissingInterpreter { path: PathBuf, interpreter: String },
+    #[error("`{0}` isn't a valid executable. Check its shebang or binary format.")]
+    InvalidExecutable(PathBuf),
+    #[error("Permission denied while executing `{0}`.")]
+    PermissionDenied(PathBuf),
+    #[error("`{0}` wasn't found.")]
+    NotFound(PathBuf),
+    #[error("Error while executing `{0}`: `{1}`.")]
+    Other(PathBuf, io::Error),
+}
+
+impl SpawnError {
+    fn new(path: &Path, err: io::Error) -> Self {
+        let path = path.to_owned();
+
+        match err.raw_os_error() {
+            // ENOENT for an existing file means that its interpreter is missing.
+            Some(libc::ENOENT) => match Self::interpreter(&path) {
+                Some(interpreter) => Self::MissingInterpreter { path, interpreter },
+                None => Self::NotFound(path),
+            },
+            Some(libc::ENOEXEC) => Self::InvalidExecutable(path),
+            Some(libc::EACCES) => Self::PermissionDenied(path),
+            _ => Self::Other(path, err),
+        }
+    }
+
+    /// Returns the interpreter from the shebang of `path`, if it has one.
+    fn interpreter(path: &Path) -> Option<String> {
+        let contents = fs::read(path).ok()?;
+        let first_line = contents.split(|byte| *byte == b'\n').next()?;
+        let shebang = first_line.strip_prefix(b"#!")?;
+
+        String::from_utf8_lossy(shebang)
+            .split_whitespace()
+            .next()
+            .map(str::to_owned)
+    }
+}
+
 pub struct ProcessWrapper {
-    _process: Child,
-    stdout_lines: Lines<BufReader<ChildStdout>>,
+    process: Child,
+    stdout: BufReader<ChildStdout>,
+    stdin_sender: mpsc::Sender<String>,
+    stdin_task: JoinHandle<()>,
+    stderr_history: Arc<Mutex<VecDeque<String>>>,
+    stderr_task: Option<JoinHandle<()>>,
 }
 
 impl ProcessWrapper {
-    pub async fn new<S>(program: S) -> Self
+    async fn write_stdin(mut stdin: ChildStdin, mut lines: mpsc::Receiver<String>) {
+        while let Some(mut line) = lines.recv().await {
+            line.push('\n');
+
+            if stdin.write_all(line.as_bytes()).await.is_err() || stdin.flush().await.is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Logs every stderr line, keeping the last ones in `history`.
+    async fn read_stderr(
+        stderr: ChildStderr,
+        prefix: String,
+        history: Arc<Mutex<VecDeque<String>>>,
+    ) {
+        let mut stderr = BufReader::new(stderr);
+        let mut buffer = Vec::new();
+
+        while let Ok(1..) = stderr.read_until(b'\n', &mut buffer).await {
+            let line = String::from_utf8_lossy(&buffer).trim_end().to_owned();
+
+            buffer.clear();
+
+            warn!("{}: {}", prefix, line);
+
+            let mut history = history.lock().unwrap();
+
+            if history.len() == STDERR_HISTORY_SIZE {
+                history.pop_front();
+            }
+
+            history.push_back(line);
+        }
+    }
+
+    pub async fn new<S>(program: S) -> Result<Self, SpawnError>
     where
         S: AsRef<OsStr>,
     {
-        let mut process = Command::new(program)
+        let mut process = Command::new(&program)
             .kill_on_drop(true)
+            .stdin(Stdio::piped())
             .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
             .spawn()
-            .unwrap();
+            .map_err(|err| SpawnError::new(Path::new(program.as_ref()), err))?;
+        let (stdin_sender, stdin_receiver) = mpsc::channel(STDIN_QUEUE_SIZE);
+        let stderr_history = Arc::new(Mutex::new(VecDeque::with_capacity(STDERR_HISTORY_SIZE)));
+        let stderr_prefix = format!(
+            "Config Process `{}` ({})",
+            Path::new(program.as_ref()).display(),
+            process.id().unwrap_or_default()
+        );
 
-        Self {
-            stdout_lines: BufReader::new(process.stdout.take().unwrap()).lines(),
-            _process: process,
-        }
+        Ok(Self {
+            stdout: BufReader::new(process.stdout.take().unwrap()),
+            stdin_task: spawn(Self::write_stdin(
+                process.stdin.take().unwrap(),
+                stdin_receiver,
+            )),
+            stdin_sender,
+            stderr_task: Some(spawn(Self::read_stderr(
+                process.stderr.take().unwrap(),
+                stderr_prefix,
+                stderr_history.clone(),
+            ))),
+            stderr_history,
+            process,
+        })
     }
 
+    /// Reads the next stdout line. Invalid UTF-8 is replaced instead of failing, so a single
+    /// malformed line doesn't stop reading the process.
     pub async fn read_line(&mut self) -> io::Result<Option<String>> {
-        self.stdout_lines.next_line().await
+        let mut buffer = Vec::new();
+
+        if self.stdout.read_until(b'\n', &mut buffer).await? == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            String::from_utf8_lossy(&buffer)
+                .trim_end_matches(['\n', '\r'])
+                .to_owned(),
+        ))
+    }
+
+    /// Queues a line to be written to the process' stdin. Returns `false` if it was dropped
+    /// because the process doesn't read its stdin.
+    pub fn write_line(&self, line: String) -> bool {
+        !matches!(self.stdin_sender.try_send(line), Err(TrySendError::Full(_)))
+    }
+
+    pub async fn kill(&mut self) -> io::Result<()> {
+        self.process.kill().await
+    }
+
+    pub async fn wait(&mut self) -> io::Result<ExitStatus> {
+        self.process.wait().await
+    }
+
+    /// Returns the last stderr lines, waiting a bit for the ones the process wrote right
+    /// before exiting.
+    pub async fn recent_stderr(&mut self) -> Vec<String> {
+        if let Some(stderr_task) = self.stderr_task.as_mut() {
+            if timeout(*STDERR_DRAIN_TIMEOUT, stderr_task).await.is_ok() {
+                self.stderr_task = None;
+            }
+        }
+
+        self.stderr_history
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
+
+impl Drop for ProcessWrapper {
+    fn drop(&mut self) {
+        self.stdin_task.abort();
+
+        if let Some(stderr_task) = &self.stderr_task {
+            stderr_task.abort();
+        }
     }
 }
@@ -17,26 +17,130 @@
     along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use std::error::Error;
+use std::{error::Error, process};
 
-use discord_rich_presence::{
-    activity::{Activity, Assets, Button, Party, Timestamps},
-    DiscordIpc, DiscordIpcClient,
-};
+use discord_rich_presence::activity::{self, Activity, Assets, Button, Party, Secrets, Timestamps};
+use log::warn;
+use serde_json::{json, Value};
+use tokio::sync::broadcast;
 
-use crate::update_message::UpdateMessageItem;
+use crate::{
+    ipc_socket::{
+        IpcSocket, FORWARDED_EVENTS, OPCODE_CLOSE, OPCODE_FRAME, OPCODE_HANDSHAKE, OPCODE_PING,
+        OPCODE_PONG,
+    },
+    stdin_message::StdinMessage,
+    update_message::{ActivityType, UpdateMessageItem},
+};
 
 pub struct RichPresenceClient {
-    client: DiscordIpcClient,
+    client: IpcSocket,
+    last_nonce: u64,
 }
 
 impl RichPresenceClient {
-    pub fn new(application_id: u64) -> Result<Self, Box<dyn Error>> {
-        let mut client = DiscordIpcClient::new(&application_id.to_string())?;
+    pub async fn new(
+        application_id: u64,
+        stdin_sender: broadcast::Sender<StdinMessage>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut client = IpcSocket::new(application_id, stdin_sender);
+
+        client.connect().await?;
+        client
+            .send(
+                json!({ "v": 1, "client_id": application_id.to_string() }),
+                OPCODE_HANDSHAKE,
+            )
+            .await?;
+
+        let (opcode, payload) = client.recv().await?;
+
+        // Discord closes the connection instead of dispatching READY when it rejects the
+        // application, e.g. because of an unknown client id.
+        if opcode == OPCODE_CLOSE {
+            return Err(format!(
+                "{} (code {})",
+                payload["message"]
+                    .as_str()
+                    .unwrap_or("Handshake was rejected"),
+                payload["code"]
+            )
+            .into());
+        }
+
+        if opcode != OPCODE_FRAME || payload["cmd"] != "DISPATCH" || payload["evt"] != "READY" {
+            return Err(format!("Unexpected handshake response: `{}`", payload).into());
+        }
+
+        let mut client = Self {
+            client,
+            last_nonce: 0,
+        };
 
-        client.connect()?;
+        for event in FORWARDED_EVENTS {
+            // Not every application is allowed to receive every event, which shouldn't
+            // prevent showing its activity.
+            if let Err(err) = client
+                .send_command(json!({ "cmd": "SUBSCRIBE", "evt": event }))
+                .await
+            {
+                warn!(
+                    "Error while subscribing application {} to `{}`: `{}`.",
+                    application_id, event, err
+                );
+            }
+        }
 
-        Ok(Self { client })
+        Ok(client)
+    }
+
+    /// Sends a command with a fresh nonce and waits for the response.
+    async fn send_command(&mut self, mut command: Value) -> Result<Value, Box<dyn Error>> {
+        self.last_nonce += 1;
+        command["nonce"] = self.last_nonce.to_string().into();
+
+        self.client.send(command, OPCODE_FRAME).await?;
+
+        self.recv_response().await
+    }
+
+    async fn recv(&mut self) -> Result<(u32, Value), Box<dyn Error>> {
+        let (opcode, payload) = self.client.recv().await?;
+
+        if opcode == OPCODE_CLOSE {
+            return Err(format!("Discord closed the connection: `{}`.", payload).into());
+        }
+
+        Ok((opcode, payload))
+    }
+
+    /// Waits for Discord's response to the last sent command. Late responses to earlier
+    /// commands are skipped by their nonce.
+    async fn recv_response(&mut self) -> Result<Value, Box<dyn Error>> {
+        let nonce = self.last_nonce.to_string();
+
+        loop {
+            let (opcode, payload) = self.recv().await?;
+
+            if opcode != OPCODE_FRAME || payload["cmd"] == "DISPATCH" || payload["nonce"] != nonce {
+                continue;
+            }
+
+            if payload["evt"] == "ERROR" {
+                return Err(payload["data"]["message"].to_string().into());
+            }
+
+            return Ok(payload);
+        }
+    }
+
+    /// Checks that the connection is still alive without touching the activity.
+    pub async fn ping(&mut self) -> Result<(), Box<dyn Error>> {
+        self.client.send(json!({}), OPCODE_PING).await?;
+
+        while self.recv().await?.0 != OPCODE_PONG {}
+
+        Ok(())
     }
 
     pub async fn set_activity(
@@ -74,13 +178,36 @@ impl RichPresenceClient {
             timestamps = timestamps.start(start_timestamp);
         }
         if let Some(end_timestamp) = message.end_timestamp {
-            timestamps = timestamps.start(end_timestamp);
+            timestamps = timestamps.end(end_timestamp);
+        }
+
+        if let Some(party) = &message.party {
+            let mut party_builder = Party::new();
+
+            if let Some(id) = &party.id {
+                party_builder = party_builder.id(id.as_str());
+            }
+            if let Some(size) = party.size {
+                party_builder = party_builder.size(size);
+            }
+
+            activity = activity.party(party_builder);
         }
 
-        if let Some(party_size) = message.party {
-            let party = Party::new().size(party_size);
+        if let Some(secrets) = &message.secrets {
+            let mut secrets_builder = Secrets::new();
 
-            activity = activity.party(party);
+            if let Some(join) = &secrets.join {
+                secrets_builder = secrets_builder.join(join.as_str());
+            }
+            if let Some(spectate) = &secrets.spectate {
+                secrets_builder = secrets_builder.spectate(spectate.as_str());
+            }
+            if let Some(match_) = &secrets.match_ {
+                secrets_builder = secrets_builder.r#match(match_.as_str());
+            }
+
+            activity = activity.secrets(secrets_builder);
         }
 
         if !message.buttons.is_empty() {
@@ -91,16 +218,46 @@ impl RichPresenceClient {
             activity = activity.buttons(buttons);
         }
 
+        if let Some(activity_type) = message.activity_type {
+            activity = activity.activity_type(match activity_type {
+                ActivityType::Playing => activity::ActivityType::Playing,
+                ActivityType::Listening => activity::ActivityType::Listening,
+                ActivityType::Watching => activity::ActivityType::Watching,
+                ActivityType::Competing => activity::ActivityType::Competing,
+            });
+        }
+
         activity = activity.assets(assets).timestamps(timestamps);
-        self.client.set_activity(activity)?;
+
+        // `Activity` has no `instance` field, so the command is assembled here.
+        let mut activity = serde_json::to_value(activity)?;
+
+        if let Some(instance) = message.instance {
+            activity["instance"] = instance.into();
+        }
+
+        self.send_command(json!({
+            "cmd": "SET_ACTIVITY",
+            "args": {
+                "pid": process::id(),
+                "activity": activity,
+            },
+        }))
+        .await?;
 
         Ok(())
     }
-}
 
-#[allow(unused_must_use)]
-impl Drop for RichPresenceClient {
-    fn drop(&mut self) {
-        self.client.close();
+    pub async fn clear_activity(&mut self) -> Result<(), Box<dyn Error>> {
+        self.send_command(json!({
+            "cmd": "SET_ACTIVITY",
+            "args": {
+                "pid": process::id(),
+                "activity": null,
+            },
+        }))
+        .await?;
+
+        Ok(())
     }
 }
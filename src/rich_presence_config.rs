@@ -17,32 +17,89 @@
     along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use std::path::{Path, PathBuf};
+use std::{
+    io,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    time::{Duration, Instant},
+};
 
+use clap::ValueEnum;
 use is_executable::is_executable;
-use log::{error, info};
-use notify::{event::AccessKind, Config, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
-use serde_json::from_str;
-use tokio::{fs::read_to_string, spawn, sync::mpsc, task::JoinHandle};
-
-use crate::{process_wrapper::ProcessWrapper, update_message::UpdateMessage};
-
-async fn load_config<S>(path: S) -> Option<UpdateMessage>
-where
-    S: AsRef<Path>,
-{
-    match read_to_string(path).await {
-        Ok(config) => match from_str::<UpdateMessage>(&config) {
-            Ok(message) => return Some(message),
-            Err(err) => error!(
-                "Error while parsing config file: `{}`. Config: `{}`.",
-                err, config
-            ),
-        },
-        Err(err) => error!("Error while reading config file: `{}`.", err),
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use tokio::{fs::read_to_string, select, spawn, sync::broadcast, task::JoinHandle, time::sleep};
+
+use crate::{
+    backoff::Backoff,
+    config_sources::SourceSender,
+    config_watcher::ConfigWatcher,
+    process_wrapper::{ProcessWrapper, SpawnError},
+    stdin_message::StdinMessage,
+    template::Template,
+    update_message::{self, Config, Format, ParseError, SourceMessage, Update},
+};
+
+lazy_static! {
+    /// Config Process which ran for this long is considered healthy again, so its count of
+    /// restarts is reset.
+    static ref STABLE_RUN_TIME: Duration = Duration::from_secs(60);
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum RestartMode {
+    Never,
+    OnFailure,
+    Always,
+}
+
+/// What to do when Config Process exits.
+#[derive(Clone, Copy)]
+pub struct RestartPolicy {
+    pub mode: RestartMode,
+    /// Count of consecutive restarts after which Config Process is considered permanently
+    /// failed.
+    pub max_restarts: u32,
+    pub backoff: Backoff,
+    /// Clear presence when Config Process is considered permanently failed.
+    pub clear_on_failure: bool,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum LoadError {
+    #[error("Error while reading config file: `{0}`.")]
+    Read(#[from] io::Error),
+    #[error("Error while parsing config file as {format:?}: `{error}`.")]
+    Parse { format: Format, error: ParseError },
+    #[error("Error while expanding config file templates: `{0}`.")]
+    Expand(ParseError),
+}
+
+/// Loads static config, expanding its templates if there are any.
+pub async fn load_config(path: &Path) -> Result<(SourceMessage, Option<Template>), LoadError> {
+    let config = read_to_string(path).await?;
+    let format = Format::detect(path, &config);
+
+    match update_message::parse_config(&config, format) {
+        Ok(Config::Message(message)) => Ok((message, None)),
+        Ok(Config::Template(template)) => {
+            match update_message::from_value(template.expand().await) {
+                Ok(message) => Ok((message, Some(template))),
+                Err(err) => Err(LoadError::Expand(err)),
+            }
+        }
+        Err(error) => Err(LoadError::Parse { format, error }),
     }
+}
+
+/// Aborts the task when dropped, so Config Process doesn't outlive its config.
+struct ReaderTask(JoinHandle<()>);
 
-    None
+impl Drop for ReaderTask {
+    fn drop(&mut self) {
+        self.0.abort()
+    }
 }
 
 pub struct RichPresenceConfig {
@@ -50,76 +107,367 @@ pub struct RichPresenceConfig {
 }
 
 impl RichPresenceConfig {
-    async fn read(path: PathBuf, updates_sender: tokio::sync::mpsc::Sender<UpdateMessage>) {
-        let mut process = ProcessWrapper::new(path).await;
+    /// Reads Config Process until it exits. Returns `None` if updates aren't received anymore.
+    async fn read(
+        mut process: ProcessWrapper,
+        updates_sender: SourceSender,
+        stdin_sender: broadcast::Sender<StdinMessage>,
+    ) -> Option<ExitStatus> {
+        let mut stdin_receiver = stdin_sender.subscribe();
+        let mut is_stdin_full = false;
+        let source = updates_sender.source();
 
-        while let Ok(Some(line)) = process.read_line().await {
-            match from_str::<UpdateMessage>(&line) {
-                Ok(message) => {
-                    if updates_sender.send(message).await.is_err() {
-                        break;
+        loop {
+            select! {
+                line = process.read_line() => {
+                    let line = match line {
+                        Ok(Some(line)) => line,
+                        Ok(None) => break,
+                        Err(err) => {
+                            error!("Error while reading Config Process' stdout: `{}`.", err);
+
+                            // Otherwise waiting for it to exit could block forever.
+                            if let Err(err) = process.kill().await {
+                                error!("Error while killing Config Process: `{}`.", err);
+                            }
+
+                            break;
+                        }
+                    };
+
+                    match update_message::parse(&line) {
+                        Ok(message) => {
+                            if updates_sender.send(message).await.is_err() {
+                                return None;
+                            }
+                        }
+                        Err(err) => {
+                            error!(
+                                "Error while parsing config response: `{}`. Received value: `{}`.",
+                                err, line
+                            );
+                        }
                     }
                 }
-                Err(err) => {
-                    error!(
-                        "Error while parsing config response: `{}`. Received value: `{}`.",
-                        err, line
-                    );
+                message = stdin_receiver.recv() => {
+                    let message = match message {
+                        Ok(message) => message,
+                        Err(broadcast::error::RecvError::Lagged(count)) => {
+                            warn!("{} messages to Config Process were lost.", count);
+
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    };
+
+                    // Acks of other sources' updates mean nothing to this Config Process.
+                    if let StdinMessage::Ack { source: ack_source, .. } = &message {
+                        if *ack_source != source {
+                            continue;
+                        }
+                    }
+
+                    let was_stdin_full = is_stdin_full;
+
+                    is_stdin_full = !process.write_line(serde_json::to_string(&message).unwrap());
+
+                    if is_stdin_full && !was_stdin_full {
+                        warn!("Config Process doesn't read its stdin. Dropping messages to it.");
+                    }
                 }
             }
         }
 
-        info!("Config Process' stdout was closed (it died?). Showing last sent activity.");
+        let status = match process.wait().await {
+            Ok(status) => status,
+            Err(err) => {
+                error!("Error while waiting for Config Process to exit: `{}`.", err);
+
+                return None;
+            }
+        };
+
+        if status.success() {
+            info!("Config Process exited ({}).", status);
+        } else {
+            error!("Config Process failed ({}).", status);
+
+            let stderr = process.recent_stderr().await;
+
+            if !stderr.is_empty() {
+                error!(
+                    "Last lines of Config Process' stderr:\n{}",
+                    stderr.join("\n")
+                );
+            }
+        }
+
+        Some(status)
+    }
+
+    /// Reports that Config Process can't be started.
+    async fn spawn_failed(
+        err: SpawnError,
+        updates_sender: &SourceSender,
+        restart_policy: RestartPolicy,
+    ) {
+        // Restarting won't help until the config file is fixed.
+        error!(
+            "Error while starting Config Process: {} Waiting for config file to change.",
+            err
+        );
+
+        if restart_policy.clear_on_failure {
+            let _ = updates_sender
+                .send(Update::Message(SourceMessage::default()))
+                .await;
+        }
     }
 
-    async fn run(path: PathBuf, updates_sender: tokio::sync::mpsc::Sender<UpdateMessage>) {
-        let (tx, mut rx) = mpsc::channel(10);
-        let mut watcher = RecommendedWatcher::new(
-            move |event: notify::Result<notify::Event>| {
-                let event = event.unwrap();
+    /// Expands templates of static config every `interval`, sending the config when it
+    /// changes.
+    async fn expand(
+        template: Template,
+        mut last_message: SourceMessage,
+        updates_sender: SourceSender,
+        interval: Duration,
+    ) {
+        loop {
+            sleep(interval).await;
+
+            match update_message::from_value(template.expand().await) {
+                Ok(message) => {
+                    if message == last_message {
+                        continue;
+                    }
+
+                    last_message = message.clone();
 
-                if let EventKind::Access(AccessKind::Close(_)) = event.kind {
-                    tx.blocking_send(()).unwrap();
+                    if updates_sender.send(Update::Message(message)).await.is_err() {
+                        return;
+                    }
                 }
-            },
-            Config::default(),
-        )
-        .unwrap();
+                Err(err) => error!("Error while expanding config file templates: `{}`.", err),
+            }
+        }
+    }
+
+    /// Runs already started Config Process, restarting it according to `restart_policy`.
+    async fn supervise(
+        mut process: ProcessWrapper,
+        path: PathBuf,
+        updates_sender: SourceSender,
+        stdin_sender: broadcast::Sender<StdinMessage>,
+        restart_policy: RestartPolicy,
+    ) {
+        let mut restarts = 0;
+
+        loop {
+            let started = Instant::now();
+            let status =
+                match Self::read(process, updates_sender.clone(), stdin_sender.clone()).await {
+                    Some(status) => status,
+                    None => return,
+                };
+
+            if started.elapsed() >= *STABLE_RUN_TIME {
+                restarts = 0;
+            }
 
-        watcher.watch(&path, RecursiveMode::NonRecursive).unwrap();
+            let should_restart = match restart_policy.mode {
+                RestartMode::Never => false,
+                RestartMode::OnFailure => !status.success(),
+                RestartMode::Always => true,
+            };
 
-        let mut _reader_task: Option<JoinHandle<()>> = None;
+            if !should_restart {
+                if status.success() {
+                    info!("Showing last sent activity.");
 
-        macro_rules! reload_config {
-            () => {
-                if let Some(_reader_task) = _reader_task.take() {
-                    _reader_task.abort();
+                    return;
                 }
+            } else if restarts < restart_policy.max_restarts {
+                restarts += 1;
+
+                let delay = restart_policy.backoff.delay(restarts);
+
+                info!(
+                    "Restarting Config Process in {:.1} seconds (restart {} of {}).",
+                    delay.as_secs_f64(),
+                    restarts,
+                    restart_policy.max_restarts
+                );
+
+                sleep(delay).await;
+
+                process = match ProcessWrapper::new(path.clone()).await {
+                    Ok(process) => process,
+                    Err(err) => {
+                        Self::spawn_failed(err, &updates_sender, restart_policy).await;
 
-                if is_executable(&path) {
-                    _reader_task = Some(spawn(Self::read(path.clone(), updates_sender.clone())));
-                } else if let Some(message) = load_config(&path).await {
-                    if updates_sender.send(message).await.is_err() {
                         return;
                     }
+                };
+
+                continue;
+            }
+
+            if restart_policy.clear_on_failure {
+                error!("Config Process is considered permanently failed. Clearing activity.");
+
+                let _ = updates_sender
+                    .send(Update::Message(SourceMessage::default()))
+                    .await;
+            } else {
+                error!(
+                    "Config Process is considered permanently failed. Showing last sent activity."
+                );
+            }
+
+            return;
+        }
+    }
+
+    /// Loads the config, replacing the running one only if it's loaded successfully, so a
+    /// broken or half-written config file keeps the last good one working. Breaks if updates
+    /// aren't received anymore.
+    async fn reload(
+        path: &Path,
+        updates_sender: &SourceSender,
+        stdin_sender: &broadcast::Sender<StdinMessage>,
+        restart_policy: RestartPolicy,
+        template_interval: Duration,
+        reader_task: &mut Option<ReaderTask>,
+        is_loaded: &mut bool,
+    ) -> ControlFlow<()> {
+        if is_executable(path) {
+            match ProcessWrapper::new(path.to_owned()).await {
+                Ok(process) => {
+                    *reader_task = Some(ReaderTask(spawn(Self::supervise(
+                        process,
+                        path.to_owned(),
+                        updates_sender.clone(),
+                        stdin_sender.clone(),
+                        restart_policy,
+                    ))));
+                    *is_loaded = true;
                 }
-            };
+                Err(err) if *is_loaded => error!(
+                    "Error while starting Config Process: {} Keeping previous config.",
+                    err
+                ),
+                Err(err) => Self::spawn_failed(err, updates_sender, restart_policy).await,
+            }
+        } else {
+            match load_config(path).await {
+                Ok((message, template)) => {
+                    *reader_task = template.map(|template| {
+                        ReaderTask(spawn(Self::expand(
+                            template,
+                            message.clone(),
+                            updates_sender.clone(),
+                            template_interval,
+                        )))
+                    });
+
+                    if updates_sender.send(Update::Message(message)).await.is_err() {
+                        return ControlFlow::Break(());
+                    }
+
+                    *is_loaded = true;
+                }
+                Err(err) => {
+                    error!("{}", err);
+
+                    if *is_loaded {
+                        warn!("Keeping previous config.");
+                    }
+                }
+            }
         }
 
-        reload_config!();
+        ControlFlow::Continue(())
+    }
+
+    async fn run(
+        path: PathBuf,
+        updates_sender: SourceSender,
+        stdin_sender: broadcast::Sender<StdinMessage>,
+        restart_policy: RestartPolicy,
+        template_interval: Duration,
+        mut reload_receiver: broadcast::Receiver<()>,
+    ) {
+        let mut watcher = ConfigWatcher::new(path.clone());
+
+        // Dropping the task stops the previous Config Process or template expansion.
+        let mut reader_task = None;
+        let mut is_loaded = false;
 
         loop {
-            rx.recv().await;
+            if Self::reload(
+                &path,
+                &updates_sender,
+                &stdin_sender,
+                restart_policy,
+                template_interval,
+                &mut reader_task,
+                &mut is_loaded,
+            )
+            .await
+            .is_break()
+            {
+                return;
+            }
+
+            // Waits until the config has to be reloaded.
+            loop {
+                select! {
+                    () = watcher.changed() => {
+                        if !path.exists() {
+                            warn!("Config file was removed! Keeping current config until it appears again.");
+
+                            continue;
+                        }
+
+                        info!("Config file was changed! Reloading...");
+                    }
+                    result = reload_receiver.recv() => {
+                        if let Err(broadcast::error::RecvError::Closed) = result {
+                            return;
+                        }
 
-            info!("Config file was changed! Restarting...");
+                        if !path.exists() {
+                            warn!("Config file doesn't exist! Keeping current config.");
 
-            reload_config!();
+                            continue;
+                        }
+
+                        info!("Reload was requested! Reloading...");
+                    }
+                }
+
+                break;
+            }
         }
     }
 
-    pub fn new(path: PathBuf, updates_sender: tokio::sync::mpsc::Sender<UpdateMessage>) -> Self {
+    pub fn new(
+        path: PathBuf,
+        updates_sender: SourceSender,
+        stdin_sender: broadcast::Sender<StdinMessage>,
+        restart_policy: RestartPolicy,
+        template_interval: Duration,
+        reload_receiver: broadcast::Receiver<()>,
+    ) -> Self {
         Self {
-            task: tokio::spawn(RichPresenceConfig::run(path, updates_sender)),
+            task: tokio::spawn(RichPresenceConfig::run(
+                path,
+                updates_sender,
+                stdin_sender,
+                restart_policy,
+                template_interval,
+                reload_receiver,
+            )),
         }
     }
 }
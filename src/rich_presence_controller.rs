@@ -17,9 +17,22 @@
     along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use std::{collections::HashMap, error::Error};
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt, mem,
+    time::{Duration, Instant},
+};
 
-use crate::{rich_presence_client::RichPresenceClient, update_message::UpdateMessage};
+use log::{debug, info, warn};
+use tokio::sync::broadcast;
+
+use crate::{
+    backoff::Backoff,
+    rich_presence_client::RichPresenceClient,
+    stdin_message::{ConnectionStatus, StdinMessage},
+    update_message::{UpdateMessage, UpdateMessageItem},
+};
 
 #[derive(thiserror::Error, Debug)]
 pub enum UpdateError {
@@ -27,40 +40,292 @@ pub enum UpdateError {
     Connecting(Box<dyn Error>),
     #[error("Error while setting activity: `{0}`.")]
     ActivitySetting(Box<dyn Error>),
+    #[error("Connection to Discord was lost: `{0}`.")]
+    ConnectionLost(Box<dyn Error>),
+}
+
+/// Result of every connection attempt or activity update made by an update, by application
+/// id. Applications which are backing off or have nothing to send are not included.
+pub type UpdateReport = BTreeMap<u64, Result<(), UpdateError>>;
+
+enum ConnectionState {
+    Disconnected,
+    Connecting,
+    Connected {
+        client: RichPresenceClient,
+        /// Activity which was last sent to Discord.
+        activity: Box<UpdateMessageItem>,
+        last_heartbeat: Instant,
+    },
+    BackingOff {
+        until: Instant,
+    },
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConnectionState::Disconnected => "disconnected",
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Connected { .. } => "connected",
+            ConnectionState::BackingOff { .. } => "backing off",
+        })
+    }
+}
+
+struct Application {
+    state: ConnectionState,
+    /// Count of consecutive failed attempts.
+    failures: u32,
+}
+
+impl Application {
+    fn new() -> Self {
+        Self {
+            state: ConnectionState::Disconnected,
+            failures: 0,
+        }
+    }
 }
 
 pub struct RichPresenceController {
-    clients: HashMap<u64, RichPresenceClient>,
+    applications: HashMap<u64, Application>,
+    backoff: Backoff,
+    heartbeat_interval: Duration,
+    stdin_sender: broadcast::Sender<StdinMessage>,
 }
 
 impl RichPresenceController {
-    pub fn new() -> Self {
+    pub fn new(
+        backoff: Backoff,
+        heartbeat_interval: Duration,
+        stdin_sender: broadcast::Sender<StdinMessage>,
+    ) -> Self {
         Self {
-            clients: HashMap::new(),
+            applications: HashMap::new(),
+            backoff,
+            heartbeat_interval,
+            stdin_sender,
         }
     }
 
-    pub async fn update(&mut self, message: &UpdateMessage) -> Result<(), UpdateError> {
-        let mut new_clients = HashMap::new();
+    fn notify_state(&self, application_id: u64, state: ConnectionStatus) {
+        // There may be no Config Process to receive it, which is fine.
+        let _ = self.stdin_sender.send(StdinMessage::ConnectionState {
+            application_id,
+            state,
+        });
+    }
 
-        for item in message {
-            let mut client = if let Some(client) = self.clients.remove(&item.application_id) {
-                client
-            } else {
-                RichPresenceClient::new(item.application_id)
-                    .map_err(|err| UpdateError::Connecting(err))?
-            };
+    async fn connect(&self, item: &UpdateMessageItem) -> Result<RichPresenceClient, UpdateError> {
+        let mut client = RichPresenceClient::new(item.application_id, self.stdin_sender.clone())
+            .await
+            .map_err(UpdateError::Connecting)?;
+
+        client
+            .set_activity(item)
+            .await
+            .map_err(UpdateError::ActivitySetting)?;
+
+        Ok(client)
+    }
+
+    async fn update_application(
+        &self,
+        application: &mut Application,
+        item: &UpdateMessageItem,
+        now: Instant,
+    ) -> Option<Result<(), UpdateError>> {
+        let application_id = item.application_id;
+        let was_connected = matches!(application.state, ConnectionState::Connected { .. });
+        let was_backing_off = matches!(application.state, ConnectionState::BackingOff { .. });
+        let previous_state = mem::replace(&mut application.state, ConnectionState::Disconnected);
+
+        let result = match previous_state {
+            ConnectionState::BackingOff { until } if now < until => {
+                application.state = previous_state;
 
-            client
+                return None;
+            }
+            ConnectionState::Connected {
+                mut client,
+                activity,
+                last_heartbeat,
+            } if *activity == *item => {
+                if now < last_heartbeat + self.heartbeat_interval {
+                    application.state = ConnectionState::Connected {
+                        client,
+                        activity,
+                        last_heartbeat,
+                    };
+
+                    return None;
+                }
+
+                client
+                    .ping()
+                    .await
+                    .map(|()| client)
+                    .map_err(UpdateError::ConnectionLost)
+            }
+            ConnectionState::Connected { mut client, .. } => client
                 .set_activity(item)
                 .await
-                .map_err(|err| UpdateError::ActivitySetting(err))?;
+                .map(|()| client)
+                .map_err(UpdateError::ActivitySetting),
+            ConnectionState::Disconnected
+            | ConnectionState::Connecting
+            | ConnectionState::BackingOff { .. } => {
+                debug!(
+                    "Application {}: {} -> {}.",
+                    application_id,
+                    previous_state,
+                    ConnectionState::Connecting
+                );
+
+                application.state = ConnectionState::Connecting;
+
+                self.connect(item).await
+            }
+        };
+
+        match result {
+            Ok(client) => {
+                if !was_connected {
+                    info!("Application {}: connected to Discord.", application_id);
+
+                    self.notify_state(application_id, ConnectionStatus::Connected);
+                }
 
-            new_clients.insert(item.application_id, client);
+                application.failures = 0;
+                application.state = ConnectionState::Connected {
+                    client,
+                    activity: Box::new(item.clone()),
+                    last_heartbeat: now,
+                };
+
+                Some(Ok(()))
+            }
+            Err(err) => {
+                application.failures += 1;
+
+                let delay = self.backoff.delay(application.failures);
+
+                warn!(
+                    "Application {}: {} Backing off, retrying in {:.1} seconds (attempt {}).",
+                    application_id,
+                    err,
+                    delay.as_secs_f64(),
+                    application.failures
+                );
+
+                application.state = ConnectionState::BackingOff { until: now + delay };
+
+                if !was_backing_off {
+                    self.notify_state(application_id, ConnectionStatus::BackingOff);
+                }
+
+                Some(Err(err))
+            }
+        }
+    }
+
+    /// Applies the message to every application independently. Failed applications back off
+    /// and are retried by later updates once their delay expires.
+    pub async fn update(&mut self, message: &UpdateMessage) -> UpdateReport {
+        let now = Instant::now();
+        let mut new_applications = HashMap::new();
+        let mut report = UpdateReport::new();
+
+        for item in message {
+            let mut application = self
+                .applications
+                .remove(&item.application_id)
+                .unwrap_or_else(Application::new);
+
+            if let Some(result) = self.update_application(&mut application, item, now).await {
+                report.insert(item.application_id, result);
+            }
+
+            new_applications.insert(item.application_id, application);
+        }
+
+        // Clients which are absent from the message are cleared explicitly: closing the
+        // connection alone doesn't always remove the status from Discord.
+        let removed_applications = mem::replace(&mut self.applications, new_applications);
+
+        for (application_id, application) in removed_applications {
+            if let ConnectionState::Connected { mut client, .. } = application.state {
+                if let Err(err) = client.clear_activity().await {
+                    warn!(
+                        "Error while clearing activity of application {}: `{}`.",
+                        application_id, err
+                    );
+                }
+
+                info!("Application {}: disconnected from Discord.", application_id);
+            }
+
+            self.notify_state(application_id, ConnectionStatus::Disconnected);
+        }
+
+        report
+    }
+
+    pub fn is_connected(&self, application_id: u64) -> bool {
+        matches!(
+            self.applications.get(&application_id),
+            Some(Application {
+                state: ConnectionState::Connected { .. },
+                ..
+            })
+        )
+    }
+
+    pub fn connection_status(&self, application_id: u64) -> ConnectionStatus {
+        match self.applications.get(&application_id) {
+            Some(Application {
+                state: ConnectionState::Connected { .. },
+                ..
+            }) => ConnectionStatus::Connected,
+            Some(Application {
+                state: ConnectionState::BackingOff { .. },
+                ..
+            }) => ConnectionStatus::BackingOff,
+            _ => ConnectionStatus::Disconnected,
         }
+    }
 
-        self.clients = new_clients;
+    /// Returns the moment when the next update has something to do: a retry or a heartbeat.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.applications
+            .values()
+            .filter_map(|application| match &application.state {
+                ConnectionState::Connected { last_heartbeat, .. } => {
+                    Some(*last_heartbeat + self.heartbeat_interval)
+                }
+                ConnectionState::BackingOff { until } => Some(*until),
+                ConnectionState::Disconnected | ConnectionState::Connecting => None,
+            })
+            .min()
+    }
+
+    /// Reschedules retries of every backing off application to `until` and resets their
+    /// backoff.
+    pub fn retry_at(&mut self, until: Instant) {
+        for (application_id, application) in &mut self.applications {
+            if let ConnectionState::BackingOff { .. } = application.state {
+                debug!(
+                    "Application {}: retrying in {:.1} seconds.",
+                    application_id,
+                    until
+                        .saturating_duration_since(Instant::now())
+                        .as_secs_f64()
+                );
 
-        Ok(())
+                application.state = ConnectionState::BackingOff { until };
+                application.failures = 0;
+            }
+        }
     }
 }
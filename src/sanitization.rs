@@ -0,0 +1,263 @@
+/*
+    Copyright © 2021-2022 trickybestia <trickybestia@gmail.com>
+
+    This file is part of linux-discord-rich-presence.
+
+    linux-discord-rich-presence is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    linux-discord-rich-presence is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use clap::ValueEnum;
+use log::warn;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    update_message::{UpdateMessage, UpdateMessageItem},
+    validation::{
+        self, is_valid_image_key, is_valid_url, BUTTON_LABEL_LENGTH, BUTTON_URL_LENGTH,
+        IDENTIFIER_MAX_LENGTH, IMAGE_KEY_LENGTH, MAX_BUTTONS, TEXT_LENGTH,
+    },
+};
+
+/// Blank character which Discord doesn't trim, used to pad too short texts.
+const PADDING: char = '\u{2800}';
+
+/// What to do with activities which violate Discord's limits.
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum SanitizeMode {
+    /// Reject such activities.
+    Strict,
+    /// Fix such activities, logging what was changed.
+    Lenient,
+}
+
+/// Truncates `text` to at most `max` characters without splitting graphemes.
+fn truncate(text: &mut String, max: usize) {
+    let mut length = 0;
+    let mut end = 0;
+
+    for (i, grapheme) in text.grapheme_indices(true) {
+        length += grapheme.chars().count();
+
+        if length > max {
+            break;
+        }
+
+        end = i + grapheme.len();
+    }
+
+    text.truncate(end);
+}
+
+/// Fits length of `text` into `(min, max)`. Empty texts are removed, since Discord treats them
+/// as errors rather than as absent.
+fn fit_text(text: &mut Option<String>, field: &str, (min, max): (usize, usize)) -> Option<String> {
+    let value = text.as_mut()?;
+    let length = value.chars().count();
+
+    if length == 0 {
+        *text = None;
+
+        Some(format!("empty `{}` was removed", field))
+    } else if length < min {
+        value.extend(std::iter::repeat_n(PADDING, min - length));
+
+        Some(format!("`{}` was padded to {} characters", field, min))
+    } else if length > max {
+        truncate(value, max);
+
+        if value.is_empty() {
+            *text = None;
+
+            return Some(format!(
+                "`{}` was removed, since it's a single long grapheme",
+                field
+            ));
+        }
+
+        Some(format!(
+            "`{}` was truncated to {} characters",
+            field,
+            value.chars().count()
+        ))
+    } else {
+        None
+    }
+}
+
+/// Removes `identifier` if it's empty or too long. Unlike texts, identifiers are never
+/// changed, since Discord gives them back in events like `ACTIVITY_JOIN`.
+fn check_identifier(identifier: &mut Option<String>, field: &str) -> Option<String> {
+    let length = identifier.as_ref()?.chars().count();
+
+    if length == 0 {
+        *identifier = None;
+
+        Some(format!("empty `{}` was removed", field))
+    } else if length > IDENTIFIER_MAX_LENGTH {
+        *identifier = None;
+
+        Some(format!(
+            "`{}` was removed, since it's longer than {} characters",
+            field, IDENTIFIER_MAX_LENGTH
+        ))
+    } else {
+        None
+    }
+}
+
+/// Brings `item` within Discord's limits. Returns descriptions of changes.
+fn sanitize(item: &mut UpdateMessageItem) -> Vec<String> {
+    let mut changes = Vec::new();
+
+    changes.extend(fit_text(&mut item.state, "state", TEXT_LENGTH));
+    changes.extend(fit_text(&mut item.details, "details", TEXT_LENGTH));
+
+    for (field, image) in [
+        ("large_image", &mut item.large_image),
+        ("small_image", &mut item.small_image),
+    ] {
+        if let Some(some_image) = image {
+            if !is_valid_image_key(&some_image.key)
+                || some_image.key.chars().count() > IMAGE_KEY_LENGTH.1
+            {
+                *image = None;
+                changes.push(format!("`{}` with invalid key was removed", field));
+
+                continue;
+            }
+
+            changes.extend(fit_text(
+                &mut some_image.text,
+                &format!("{}.text", field),
+                TEXT_LENGTH,
+            ));
+        }
+    }
+
+    if let Some(party) = &mut item.party {
+        changes.extend(check_identifier(&mut party.id, "party.id"));
+
+        if let Some([current, max]) = party.size {
+            if current < 1 || max < 1 || current > max {
+                party.size = None;
+                changes.push(format!(
+                    "invalid party size {} of {} was removed",
+                    current, max
+                ));
+            }
+        }
+    }
+
+    if let Some(secrets) = &mut item.secrets {
+        changes.extend(check_identifier(&mut secrets.join, "secrets.join"));
+        changes.extend(check_identifier(&mut secrets.spectate, "secrets.spectate"));
+        changes.extend(check_identifier(&mut secrets.match_, "secrets.match"));
+    }
+
+    if let (Some(start), Some(end)) = (item.start_timestamp, item.end_timestamp) {
+        if end < start {
+            item.end_timestamp = None;
+            changes.push(format!(
+                "end timestamp {} before start timestamp {} was removed",
+                end, start
+            ));
+        }
+    }
+
+    item.buttons.retain_mut(|button| {
+        if !is_valid_url(&button.url) || button.url.chars().count() > BUTTON_URL_LENGTH.1 {
+            changes.push(format!(
+                "button `{}` with invalid URL `{}` was removed",
+                button.label, button.url
+            ));
+
+            return false;
+        }
+
+        if button.label.chars().count() > BUTTON_LABEL_LENGTH.1 {
+            truncate(&mut button.label, BUTTON_LABEL_LENGTH.1);
+            changes.push(format!("button label was truncated to `{}`", button.label));
+        }
+
+        if button.label.is_empty() {
+            changes.push("button with empty label was removed".to_owned());
+
+            return false;
+        }
+
+        true
+    });
+
+    if item.buttons.len() > MAX_BUTTONS {
+        changes.push(format!(
+            "{} extra buttons were removed",
+            item.buttons.len() - MAX_BUTTONS
+        ));
+        item.buttons.truncate(MAX_BUTTONS);
+    }
+
+    // Merged activities can get secrets and buttons from different sources.
+    if item.secrets.is_some() && !item.buttons.is_empty() {
+        item.buttons.clear();
+        changes
+            .push("buttons were removed, since Discord doesn't allow them with secrets".to_owned());
+    }
+
+    changes
+}
+
+/// Applies `mode` to every activity of `message`. Returns activities to send and the ones
+/// which were rejected, with reasons.
+pub fn apply(mode: SanitizeMode, message: UpdateMessage) -> (UpdateMessage, Vec<(u64, String)>) {
+    let mut accepted = UpdateMessage::new();
+    let mut rejected = Vec::new();
+
+    for mut item in message {
+        match mode {
+            SanitizeMode::Strict => {
+                let violations = validation::validate(&item);
+
+                if violations.is_empty() {
+                    accepted.push(item);
+                } else {
+                    let violations = violations
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ");
+
+                    rejected.push((
+                        item.application_id,
+                        format!("Activity violates Discord's limits: {}.", violations),
+                    ));
+                }
+            }
+            SanitizeMode::Lenient => {
+                let changes = sanitize(&mut item);
+
+                if !changes.is_empty() {
+                    warn!(
+                        "Activity of application {} was changed to fit Discord's limits: {}.",
+                        item.application_id,
+                        changes.join(", ")
+                    );
+                }
+
+                accepted.push(item);
+            }
+        }
+    }
+
+    (accepted, rejected)
+}
@@ -0,0 +1,63 @@
+/*
+    Copyright © 2021-2022 trickybestia <trickybestia@gmail.com>
+
+    This file is part of linux-discord-rich-presence.
+
+    linux-discord-rich-presence is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    linux-discord-rich-presence is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::config_sources::Source;
+
+/// Message written to Config Process' stdin as a single JSON line.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StdinMessage {
+    /// Event which Discord sent to one of the applications, e.g. `ACTIVITY_JOIN` when
+    /// somebody clicked "Join".
+    DiscordEvent {
+        application_id: u64,
+        event: String,
+        data: Value,
+    },
+    /// Connection of an application to Discord changed its state.
+    ConnectionState {
+        application_id: u64,
+        state: ConnectionStatus,
+    },
+    /// An application failed to connect or to update its activity.
+    Error {
+        application_id: u64,
+        message: String,
+    },
+    /// An update message from Config Process was applied. Applications which failed are
+    /// retried later. Unlike other messages, it's written only to Config Process of `source`.
+    Ack {
+        applied: Vec<u64>,
+        failed: Vec<u64>,
+        #[serde(skip)]
+        source: Source,
+    },
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionStatus {
+    Connected,
+    BackingOff,
+    /// The application was removed from the update message.
+    Disconnected,
+}
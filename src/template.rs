@@ -0,0 +1,259 @@
+/*
+    Copyright © 2021-2022 trickybestia <trickybestia@gmail.com>
+
+    This file is part of linux-discord-rich-presence.
+
+    linux-discord-rich-presence is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    linux-discord-rich-presence is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    env, fs,
+    ops::Range,
+    process::Stdio,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use lazy_static::lazy_static;
+use log::warn;
+use serde_json::Value;
+use tokio::{process::Command, time::timeout};
+
+lazy_static! {
+    /// Commands from `{cmd:...}` templates are killed after running for this long.
+    static ref COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+    /// Computed once, so that rounding doesn't change the activity on every expansion.
+    static ref UPTIME_START: Option<i64> = uptime_start();
+}
+
+fn uptime_start() -> Option<i64> {
+    let uptime = fs::read_to_string("/proc/uptime").ok()?;
+    let uptime = uptime.split_whitespace().next()?.parse::<f64>().ok()?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+
+    Some((now.as_secs_f64() - uptime).round() as i64)
+}
+
+fn is_variable(name: &str) -> bool {
+    matches!(name, "hostname" | "kernel" | "uptime_start")
+        || name.starts_with("env.")
+        || name.starts_with("cmd:")
+}
+
+/// Returns positions and names of templates in `s`. Braces inside of templates may be nested,
+/// like in `{cmd:awk '{print $1}' file}`. Braces which don't form a known variable are kept
+/// as they are.
+fn find_templates(s: &str) -> Vec<(Range<usize>, &str)> {
+    let mut templates = Vec::new();
+    let mut position = 0;
+
+    while let Some(start) = s[position..].find('{').map(|start| start + position) {
+        let mut depth = 0;
+        let mut end = None;
+
+        for (i, c) in s[start..].char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+
+                    if depth == 0 {
+                        end = Some(start + i);
+
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let end = match end {
+            Some(end) => end,
+            None => break,
+        };
+        let name = &s[start + 1..end];
+
+        if is_variable(name) {
+            templates.push((start..end + 1, name));
+            position = end + 1;
+        } else {
+            position = start + 1;
+        }
+    }
+
+    templates
+}
+
+fn collect_variables<'a>(value: &'a Value, variables: &mut BTreeSet<&'a str>) {
+    match value {
+        Value::String(s) => {
+            variables.extend(find_templates(s).into_iter().map(|(_, name)| name));
+        }
+        Value::Array(values) => {
+            for value in values {
+                collect_variables(value, variables);
+            }
+        }
+        Value::Object(values) => {
+            for value in values.values() {
+                collect_variables(value, variables);
+            }
+        }
+        _ => {}
+    }
+}
+
+async fn run_command(command: &str) -> String {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .output();
+
+    match timeout(*COMMAND_TIMEOUT, output).await {
+        Ok(Ok(output)) => {
+            // Logged like stderr of Config Process, so it's clear which command wrote it.
+            for line in String::from_utf8_lossy(&output.stderr).lines() {
+                warn!("Template command `{}`: {}", command, line);
+            }
+
+            if !output.status.success() {
+                warn!("Template command `{}` failed ({}).", command, output.status);
+            }
+
+            String::from_utf8_lossy(&output.stdout)
+                .trim_end()
+                .to_owned()
+        }
+        Ok(Err(err)) => {
+            warn!(
+                "Error while running template command `{}`: `{}`.",
+                command, err
+            );
+
+            String::new()
+        }
+        Err(_) => {
+            warn!(
+                "Template command `{}` didn't finish in {} seconds.",
+                command,
+                COMMAND_TIMEOUT.as_secs()
+            );
+
+            String::new()
+        }
+    }
+}
+
+async fn resolve(name: &str) -> String {
+    let read_proc = |path| {
+        fs::read_to_string(path)
+            .map(|value| value.trim_end().to_owned())
+            .unwrap_or_else(|err| {
+                warn!("Error while reading `{}`: `{}`.", path, err);
+
+                String::new()
+            })
+    };
+
+    match name {
+        "hostname" => read_proc("/proc/sys/kernel/hostname"),
+        "kernel" => read_proc("/proc/sys/kernel/osrelease"),
+        "uptime_start" => UPTIME_START
+            .map(|start| start.to_string())
+            .unwrap_or_default(),
+        _ => {
+            if let Some(variable) = name.strip_prefix("env.") {
+                env::var(variable).unwrap_or_default()
+            } else if let Some(command) = name.strip_prefix("cmd:") {
+                run_command(command).await
+            } else {
+                unreachable!("`{}` isn't a variable", name)
+            }
+        }
+    }
+}
+
+fn substitute(s: &str, values: &HashMap<&str, String>) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut position = 0;
+
+    for (range, name) in find_templates(s) {
+        result.push_str(&s[position..range.start]);
+        result.push_str(&values[name]);
+        position = range.end;
+    }
+
+    result.push_str(&s[position..]);
+
+    result
+}
+
+fn substitute_value(value: &mut Value, values: &HashMap<&str, String>) {
+    match value {
+        Value::String(s) => *s = substitute(s, values),
+        Value::Array(items) => {
+            for item in items {
+                substitute_value(item, values);
+            }
+        }
+        Value::Object(items) => {
+            for item in items.values_mut() {
+                substitute_value(item, values);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Config whose strings contain templates like `{hostname}`, which are expanded by the daemon.
+#[derive(Clone)]
+pub struct Template {
+    value: Value,
+}
+
+impl Template {
+    pub fn new(value: Value) -> Self {
+        Self { value }
+    }
+
+    pub fn has_templates(value: &Value) -> bool {
+        let mut variables = BTreeSet::new();
+
+        collect_variables(value, &mut variables);
+
+        !variables.is_empty()
+    }
+
+    /// Returns the config with every template replaced by its current value.
+    pub async fn expand(&self) -> Value {
+        let mut variables = BTreeSet::new();
+
+        collect_variables(&self.value, &mut variables);
+
+        let mut values = HashMap::new();
+
+        for name in variables {
+            values.insert(name, resolve(name).await);
+        }
+
+        let mut value = self.value.clone();
+
+        substitute_value(&mut value, &values);
+
+        value
+    }
+}
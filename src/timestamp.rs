@@ -0,0 +1,202 @@
+/*
+    Copyright © 2021-2022 trickybestia <trickybestia@gmail.com>
+
+    This file is part of linux-discord-rich-presence.
+
+    linux-discord-rich-presence is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    linux-discord-rich-presence is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    borrow::Cow,
+    fmt,
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::warn;
+use schemars::{json_schema, JsonSchema, Schema, SchemaGenerator};
+use serde::{de, Deserialize, Deserializer};
+use time::{
+    format_description::well_known::Iso8601, macros::format_description, OffsetDateTime,
+    PrimitiveDateTime, UtcOffset,
+};
+
+static DAEMON_START: OnceLock<i64> = OnceLock::new();
+static LOCAL_OFFSET: OnceLock<UtcOffset> = OnceLock::new();
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+/// Remembers start time of the daemon and the local time offset. The offset can be determined
+/// only while the process has a single thread, so this must be called at the very start.
+pub fn init() {
+    DAEMON_START.get_or_init(now);
+    LOCAL_OFFSET.get_or_init(|| {
+        UtcOffset::current_local_offset().unwrap_or_else(|err| {
+            warn!(
+                "Error while determining local time offset: `{}`. Timestamps without offset are treated as UTC.",
+                err
+            );
+
+            UtcOffset::UTC
+        })
+    });
+}
+
+/// Parses offset like `+25m`, `-1h` or `+1h30m` into seconds. Numbers without unit are seconds.
+fn parse_relative(s: &str) -> Option<i64> {
+    let (sign, mut rest) = match s.as_bytes().first()? {
+        b'+' => (1, &s[1..]),
+        b'-' => (-1, &s[1..]),
+        _ => return None,
+    };
+    let mut seconds = 0i64;
+
+    if rest.is_empty() {
+        return None;
+    }
+
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+
+        if digits == 0 {
+            return None;
+        }
+
+        let value = rest[..digits].parse::<i64>().ok()?;
+        let (unit, next) = match rest[digits..].chars().next() {
+            Some('s') => (1, &rest[digits + 1..]),
+            Some('m') => (60, &rest[digits + 1..]),
+            Some('h') => (60 * 60, &rest[digits + 1..]),
+            Some('d') => (24 * 60 * 60, &rest[digits + 1..]),
+            None => (1, ""),
+            Some(_) => return None,
+        };
+
+        seconds = seconds.checked_add(value.checked_mul(unit)?)?;
+        rest = next;
+    }
+
+    Some(sign * seconds)
+}
+
+/// Parses ISO-8601 date and time. Without offset, the time is considered local.
+fn parse_iso8601(s: &str) -> Option<i64> {
+    if let Ok(date_time) = OffsetDateTime::parse(s, &Iso8601::DEFAULT) {
+        return Some(date_time.unix_timestamp());
+    }
+
+    let formats = [
+        format_description!("[year]-[month]-[day]T[hour]:[minute]:[second]"),
+        format_description!("[year]-[month]-[day]T[hour]:[minute]"),
+        format_description!("[year]-[month]-[day] [hour]:[minute]:[second]"),
+        format_description!("[year]-[month]-[day] [hour]:[minute]"),
+    ];
+    let offset = LOCAL_OFFSET.get().copied().unwrap_or(UtcOffset::UTC);
+
+    formats.iter().find_map(|format| {
+        PrimitiveDateTime::parse(s, format)
+            .ok()
+            .map(|date_time| date_time.assume_offset(offset).unix_timestamp())
+    })
+}
+
+/// Resolves textual timestamp into Unix time in seconds.
+pub fn parse(s: &str) -> Option<i64> {
+    let s = s.trim();
+
+    match s {
+        "now" => Some(now()),
+        "daemon_start" => Some(*DAEMON_START.get_or_init(now)),
+        // Signed numbers are offsets, even though they would parse as Unix time.
+        _ if s.starts_with(['+', '-']) => parse_relative(s).map(|offset| now() + offset),
+        _ => s.parse::<i64>().ok().or_else(|| parse_iso8601(s)),
+    }
+}
+
+/// Unix time in seconds, which can also be given as ISO-8601 date and time, offset from now
+/// like `+25m`, `now` or `daemon_start`. Textual forms are resolved when the message is parsed.
+pub struct Timestamp(pub i64);
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl de::Visitor<'_> for Visitor {
+            type Value = Timestamp;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str(
+                    "Unix time, ISO-8601 date and time, offset like `+25m`, `now` or `daemon_start`",
+                )
+            }
+
+            fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Ok(Timestamp(value))
+            }
+
+            fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                i64::try_from(value)
+                    .map(Timestamp)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(value), &self))
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                parse(value)
+                    .map(Timestamp)
+                    .ok_or_else(|| E::invalid_value(de::Unexpected::Str(value), &self))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl JsonSchema for Timestamp {
+    fn schema_name() -> Cow<'static, str> {
+        "Timestamp".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "description": "Unix time in seconds, ISO-8601 date and time (local if offset isn't given), offset from now like `+25m`, `-1h` or `+1h30m`, `now` or `daemon_start`.",
+            "type": ["integer", "string"],
+        })
+    }
+}
+
+/// Deserializes optional [`Timestamp`] into Unix time.
+pub fn deserialize_option<'de, D>(deserializer: D) -> Result<Option<i64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::<Timestamp>::deserialize(deserializer)?.map(|timestamp| timestamp.0))
+}
@@ -17,40 +17,487 @@
     along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use serde::Deserialize;
+use std::{borrow::Cow, fmt, path::Path};
+
+use schemars::{json_schema, JsonSchema, Schema, SchemaGenerator};
+use serde::{
+    de::{
+        self,
+        value::{MapAccessDeserializer, SeqAccessDeserializer},
+        MapAccess, SeqAccess,
+    },
+    Deserialize, Deserializer, Serialize,
+};
+
+use crate::{
+    template::Template,
+    timestamp::{self, Timestamp},
+    validation::MAX_BUTTONS,
+};
 
 pub type UpdateMessage = Vec<UpdateMessageItem>;
 
+#[derive(thiserror::Error, Debug)]
+pub enum ParseError {
+    #[error("{0}")]
+    Json(#[from] serde_json::Error),
+    #[error("{0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("{0}")]
+    Yaml(#[from] serde_norway::Error),
+    #[error("patches can be sent only by Config Process")]
+    PatchInConfig,
+    #[error("application {0} has both secrets and buttons, which Discord doesn't allow")]
+    SecretsWithButtons(u64),
+}
+
+/// Checks constraints which can't be expressed by the type of update message.
+fn check(activities: &UpdateMessage) -> Result<(), ParseError> {
+    for item in activities {
+        if item.secrets.is_some() && !item.buttons.is_empty() {
+            return Err(ParseError::SecretsWithButtons(item.application_id));
+        }
+    }
+
+    Ok(())
+}
+
+/// Used to tell patches from full update messages.
 #[derive(Deserialize)]
+struct Op {
+    #[serde(default)]
+    op: Option<serde_json::Value>,
+}
+
+/// Parses JSON update message.
+pub fn parse(s: &str) -> Result<Update, ParseError> {
+    if !s.trim_start().starts_with('[') && serde_json::from_str::<Op>(s)?.op.is_some() {
+        return Ok(Update::Patch(serde_json::from_str(s)?));
+    }
+
+    let message = serde_json::from_str::<SourceMessage>(s)?;
+
+    check(&message.activities)?;
+
+    Ok(Update::Message(message))
+}
+
+/// Format of a static config file.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    /// Detects format by extension of `path` or, if it's unknown, by `contents`.
+    pub fn detect(path: &Path, contents: &str) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => return Self::Json,
+            Some("toml") => return Self::Toml,
+            Some("yaml" | "yml") => return Self::Yaml,
+            _ => {}
+        }
+
+        // JSON has no comments, so the first meaningful line tells the format.
+        let line = contents
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'));
+        let line = match line {
+            Some(line) => line,
+            None => return Self::Json,
+        };
+
+        if line.starts_with('{') {
+            return Self::Json;
+        }
+
+        if let Some(rest) = line.strip_prefix('[') {
+            // `[[activities]]` is a TOML table header, `[{` starts a JSON array.
+            return if rest
+                .trim_start_matches('[')
+                .starts_with(char::is_alphabetic)
+            {
+                Self::Toml
+            } else {
+                Self::Json
+            };
+        }
+
+        let is_toml_key = line.split_once('=').is_some_and(|(key, _)| {
+            let key = key.trim();
+
+            !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        });
+
+        if is_toml_key {
+            Self::Toml
+        } else {
+            Self::Yaml
+        }
+    }
+}
+
+/// Contents of a static config file.
+pub enum Config {
+    Message(SourceMessage),
+    /// Config which has to be expanded by [`Template::expand`] and [`from_value`].
+    Template(Template),
+}
+
+/// Parses static config. Patches aren't allowed here, since there is nothing to apply them to.
+pub fn parse_config(s: &str, format: Format) -> Result<Config, ParseError> {
+    let value = match format {
+        Format::Json => serde_json::from_str(s)?,
+        Format::Toml => toml::from_str(s)?,
+        Format::Yaml => serde_norway::from_str(s)?,
+    };
+
+    if Template::has_templates(&value) {
+        return Ok(Config::Template(Template::new(value)));
+    }
+
+    // Parsing the text again keeps positions in error messages.
+    let message = match format {
+        Format::Json => match parse(s)? {
+            Update::Message(message) => message,
+            Update::Patch(_) => return Err(ParseError::PatchInConfig),
+        },
+        Format::Toml => toml::from_str(s)?,
+        Format::Yaml => serde_norway::from_str(s)?,
+    };
+
+    check(&message.activities)?;
+
+    Ok(Config::Message(message))
+}
+
+/// Parses static config with expanded templates.
+pub fn from_value(value: serde_json::Value) -> Result<SourceMessage, ParseError> {
+    if value.get("op").is_some() {
+        return Err(ParseError::PatchInConfig);
+    }
+
+    let message = serde_json::from_value::<SourceMessage>(value)?;
+
+    check(&message.activities)?;
+
+    Ok(message)
+}
+
+/// Message received from a config.
+pub enum Update {
+    /// Replaces everything the config has sent before.
+    Message(SourceMessage),
+    /// Changes a single activity of the config.
+    Patch(Patch),
+}
+
+impl JsonSchema for Update {
+    fn schema_name() -> Cow<'static, str> {
+        "Update".into()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "oneOf": [
+                generator.subschema_for::<SourceMessage>(),
+                generator.subschema_for::<Patch>(),
+            ],
+        })
+    }
+}
+
+/// Returns JSON Schema of messages which are accepted from configs.
+pub fn schema() -> Schema {
+    schemars::schema_for!(Update)
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Patch {
+    /// Sets fields which are given, adding the activity if there is none.
+    Patch(Box<UpdateMessageItem>),
+    /// Removes activity of an application.
+    Remove { application_id: u64 },
+}
+
+/// How activities of a source are combined with the ones of sources with lower priority.
+#[derive(Deserialize, JsonSchema, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeMode {
+    /// Activities of lower priority sources are dropped.
+    Replace,
+    /// Activities replace the ones of lower priority sources with the same application id.
+    #[default]
+    Override,
+    /// Fields which are set replace the ones of lower priority sources with the same
+    /// application id, the rest are kept.
+    Overlay,
+}
+
+/// Update message of a single source. Can be given either as a list of activities or as an
+/// object with `priority` and `merge` mode.
+#[derive(Clone, PartialEq, Default)]
+pub struct SourceMessage {
+    pub priority: i32,
+    pub merge: MergeMode,
+    pub activities: UpdateMessage,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+struct SourceMessageObject {
+    #[serde(default)]
+    priority: i32,
+    #[serde(default)]
+    merge: MergeMode,
+    activities: UpdateMessage,
+}
+
+impl JsonSchema for SourceMessage {
+    fn schema_name() -> Cow<'static, str> {
+        "SourceMessage".into()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "oneOf": [
+                generator.subschema_for::<UpdateMessage>(),
+                generator.subschema_for::<SourceMessageObject>(),
+            ],
+        })
+    }
+}
+
+/// Unlike an untagged enum, keeps positions of errors inside of the message.
+impl<'de> Deserialize<'de> for SourceMessage {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = SourceMessage;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a list of activities or an object with `activities` field")
+            }
+
+            fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                Ok(SourceMessage {
+                    activities: Deserialize::deserialize(SeqAccessDeserializer::new(seq))?,
+                    ..SourceMessage::default()
+                })
+            }
+
+            fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let message = SourceMessageObject::deserialize(MapAccessDeserializer::new(map))?;
+
+                Ok(SourceMessage {
+                    priority: message.priority,
+                    merge: message.merge,
+                    activities: message.activities,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl Patch {
+    pub fn application_id(&self) -> u64 {
+        match self {
+            Patch::Patch(item) => item.application_id,
+            Patch::Remove { application_id } => *application_id,
+        }
+    }
+}
+
+impl SourceMessage {
+    /// Applies `patch` to activities, keeping them unchanged if the result is invalid.
+    pub fn apply(&mut self, patch: Patch) -> Result<(), ParseError> {
+        let mut activities = self.activities.clone();
+
+        match patch {
+            Patch::Patch(item) => {
+                match activities
+                    .iter_mut()
+                    .find(|activity| activity.application_id == item.application_id)
+                {
+                    Some(activity) => activity.overlay(&item),
+                    None => activities.push(*item),
+                }
+            }
+            Patch::Remove { application_id } => {
+                activities.retain(|activity| activity.application_id != application_id)
+            }
+        }
+
+        check(&activities)?;
+
+        self.activities = activities;
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, PartialEq)]
+#[schemars(deny_unknown_fields)]
+#[schemars(extend("not" = {
+    "description": "Discord doesn't allow secrets and buttons at the same time.",
+    "required": ["secrets", "buttons"],
+    "properties": { "buttons": { "minItems": 1 } }
+}))]
 pub struct UpdateMessageItem {
     pub application_id: u64,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub state: Option<String>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub details: Option<String>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub large_image: Option<Image>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub small_image: Option<Image>,
-    #[serde(default)]
+    #[serde(
+        default,
+        deserialize_with = "timestamp::deserialize_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schemars(with = "Option<Timestamp>")]
     pub start_timestamp: Option<i64>,
-    #[serde(default)]
+    #[serde(
+        default,
+        deserialize_with = "timestamp::deserialize_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schemars(with = "Option<Timestamp>")]
     pub end_timestamp: Option<i64>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schemars(length(max = MAX_BUTTONS))]
     pub buttons: Vec<Button>,
-    #[serde(default)]
-    pub party: Option<[i32; 2]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub party: Option<Party>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secrets: Option<Secrets>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub activity_type: Option<ActivityType>,
 }
 
-#[derive(Deserialize)]
+impl UpdateMessageItem {
+    /// Replaces fields of `self` with the ones which are set in `other`.
+    pub fn overlay(&mut self, other: &Self) {
+        macro_rules! overlay {
+            ($($field:ident),*) => {
+                $(
+                    if other.$field.is_some() {
+                        self.$field = other.$field.clone();
+                    }
+                )*
+            };
+        }
+
+        overlay!(
+            state,
+            details,
+            large_image,
+            small_image,
+            start_timestamp,
+            end_timestamp,
+            party,
+            secrets,
+            instance,
+            activity_type
+        );
+
+        if !other.buttons.is_empty() {
+            self.buttons = other.buttons.clone();
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, PartialEq)]
+#[schemars(deny_unknown_fields)]
 pub struct Button {
     pub label: String,
     pub url: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize, JsonSchema, Clone, PartialEq)]
+#[schemars(deny_unknown_fields)]
 pub struct Image {
     pub key: String,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub text: Option<String>,
 }
+
+/// Party can be given either as `[current size, max size]` or as an object with id and size.
+#[derive(Deserialize, Serialize, JsonSchema, Clone, PartialEq)]
+#[serde(from = "PartyRepr")]
+#[schemars(with = "PartyRepr")]
+pub struct Party {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<[i32; 2]>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+#[schemars(deny_unknown_fields)]
+enum PartyRepr {
+    Size([i32; 2]),
+    Party {
+        #[serde(default)]
+        id: Option<String>,
+        #[serde(default)]
+        size: Option<[i32; 2]>,
+    },
+}
+
+impl From<PartyRepr> for Party {
+    fn from(party: PartyRepr) -> Self {
+        match party {
+            PartyRepr::Size(size) => Self {
+                id: None,
+                size: Some(size),
+            },
+            PartyRepr::Party { id, size } => Self { id, size },
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, PartialEq)]
+#[schemars(deny_unknown_fields)]
+pub struct Secrets {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub join: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spectate: Option<String>,
+    #[serde(default, rename = "match", skip_serializing_if = "Option::is_none")]
+    pub match_: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivityType {
+    Playing,
+    Listening,
+    Watching,
+    Competing,
+}
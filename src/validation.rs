@@ -0,0 +1,219 @@
+/*
+    Copyright © 2021-2022 trickybestia <trickybestia@gmail.com>
+
+    This file is part of linux-discord-rich-presence.
+
+    linux-discord-rich-presence is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    linux-discord-rich-presence is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::update_message::UpdateMessageItem;
+
+/// Allowed length of texts like state, details and image hover texts.
+pub const TEXT_LENGTH: (usize, usize) = (2, 128);
+/// Maximal length of identifiers like party id and secrets, which may be as short as needed.
+pub const IDENTIFIER_MAX_LENGTH: usize = 128;
+pub const BUTTON_LABEL_LENGTH: (usize, usize) = (1, 32);
+pub const BUTTON_URL_LENGTH: (usize, usize) = (1, 512);
+pub const IMAGE_KEY_LENGTH: (usize, usize) = (1, 256);
+pub const MAX_BUTTONS: usize = 2;
+
+/// Activity's violation of Discord's limits.
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum Violation {
+    #[error("`{field}` is {length} characters long, but it must be from {min} to {max}")]
+    Length {
+        field: String,
+        length: usize,
+        min: usize,
+        max: usize,
+    },
+    #[error("`{field}` is {length} characters long, but it must be at most {max}")]
+    TooLong {
+        field: String,
+        length: usize,
+        max: usize,
+    },
+    #[error("there are {0} buttons, but at most {MAX_BUTTONS} are allowed")]
+    TooManyButtons(usize),
+    #[error("`{field}` isn't a valid URL: `{url}`")]
+    InvalidUrl { field: String, url: String },
+    #[error("`{field}` isn't a valid image key: `{key}`")]
+    InvalidImageKey { field: String, key: String },
+    #[error("end timestamp {end} is before start timestamp {start}")]
+    TimestampsOrder { start: i64, end: i64 },
+    #[error("party size {current} of {max} is invalid")]
+    PartySize { current: i32, max: i32 },
+    #[error("there are both secrets and buttons, which Discord doesn't allow")]
+    SecretsWithButtons,
+}
+
+/// Checks that `url` is an absolute HTTP(S) URL.
+pub fn is_valid_url(url: &str) -> bool {
+    let rest = match url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+    {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+
+    !host.is_empty() && !url.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+/// Image key is either a name of an uploaded asset or an URL of an image.
+pub fn is_valid_image_key(key: &str) -> bool {
+    if key.starts_with("http://") || key.starts_with("https://") {
+        is_valid_url(key)
+    } else {
+        !key.chars().any(|c| c.is_whitespace() || c.is_control())
+    }
+}
+
+fn check_length(
+    violations: &mut Vec<Violation>,
+    field: &str,
+    value: &str,
+    (min, max): (usize, usize),
+) {
+    let length = value.chars().count();
+
+    if length < min || length > max {
+        violations.push(Violation::Length {
+            field: field.to_owned(),
+            length,
+            min,
+            max,
+        });
+    }
+}
+
+/// Returns every limit which `item` violates.
+pub fn validate(item: &UpdateMessageItem) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let large_image = item.large_image.as_ref();
+    let small_image = item.small_image.as_ref();
+    let secrets = item.secrets.as_ref();
+    let texts = [
+        ("state", item.state.as_deref()),
+        ("details", item.details.as_deref()),
+        (
+            "large_image.text",
+            large_image.and_then(|image| image.text.as_deref()),
+        ),
+        (
+            "small_image.text",
+            small_image.and_then(|image| image.text.as_deref()),
+        ),
+    ];
+    let identifiers = [
+        (
+            "party.id",
+            item.party.as_ref().and_then(|party| party.id.as_deref()),
+        ),
+        (
+            "secrets.join",
+            secrets.and_then(|secrets| secrets.join.as_deref()),
+        ),
+        (
+            "secrets.spectate",
+            secrets.and_then(|secrets| secrets.spectate.as_deref()),
+        ),
+        (
+            "secrets.match",
+            secrets.and_then(|secrets| secrets.match_.as_deref()),
+        ),
+    ];
+
+    for (field, value) in texts {
+        if let Some(value) = value {
+            check_length(&mut violations, field, value, TEXT_LENGTH);
+        }
+    }
+
+    for (field, value) in identifiers {
+        if let Some(value) = value {
+            let length = value.chars().count();
+
+            if length > IDENTIFIER_MAX_LENGTH {
+                violations.push(Violation::TooLong {
+                    field: field.to_owned(),
+                    length,
+                    max: IDENTIFIER_MAX_LENGTH,
+                });
+            }
+        }
+    }
+
+    for (field, image) in [
+        ("large_image.key", large_image),
+        ("small_image.key", small_image),
+    ] {
+        if let Some(image) = image {
+            check_length(&mut violations, field, &image.key, IMAGE_KEY_LENGTH);
+
+            if !is_valid_image_key(&image.key) {
+                violations.push(Violation::InvalidImageKey {
+                    field: field.to_owned(),
+                    key: image.key.clone(),
+                });
+            }
+        }
+    }
+
+    if item.buttons.len() > MAX_BUTTONS {
+        violations.push(Violation::TooManyButtons(item.buttons.len()));
+    }
+
+    for (i, button) in item.buttons.iter().enumerate() {
+        check_length(
+            &mut violations,
+            &format!("buttons[{}].label", i),
+            &button.label,
+            BUTTON_LABEL_LENGTH,
+        );
+        check_length(
+            &mut violations,
+            &format!("buttons[{}].url", i),
+            &button.url,
+            BUTTON_URL_LENGTH,
+        );
+
+        if !is_valid_url(&button.url) {
+            violations.push(Violation::InvalidUrl {
+                field: format!("buttons[{}].url", i),
+                url: button.url.clone(),
+            });
+        }
+    }
+
+    // Single update messages can't have both, but merged activities can.
+    if item.secrets.is_some() && !item.buttons.is_empty() {
+        violations.push(Violation::SecretsWithButtons);
+    }
+
+    if let (Some(start), Some(end)) = (item.start_timestamp, item.end_timestamp) {
+        if end < start {
+            violations.push(Violation::TimestampsOrder { start, end });
+        }
+    }
+
+    if let Some([current, max]) = item.party.as_ref().and_then(|party| party.size) {
+        if current < 1 || max < 1 || current > max {
+            violations.push(Violation::PartySize { current, max });
+        }
+    }
+
+    violations
+}
@@ -0,0 +1,195 @@
+/*
+    Copyright © 2021-2022 trickybestia <trickybestia@gmail.com>
+
+    This file is part of linux-discord-rich-presence.
+
+    linux-discord-rich-presence is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    linux-discord-rich-presence is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+mod common;
+
+use std::{path::Path, process::Command};
+
+use common::write_config;
+
+/// Runs `check` subcommand, returning whether it succeeded and its output.
+fn check(config: &Path, args: &[&str]) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_linux-discord-rich-presence"))
+        .arg("check")
+        .arg(config)
+        .args(args)
+        .output()
+        .unwrap();
+
+    (
+        output.status.success(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+#[test]
+fn valid_static_config_passes() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = write_config(
+        dir.path(),
+        "config.json",
+        r#"[{
+            "application_id": 42,
+            "state": "Some state",
+            "large_image": { "key": "large" },
+            "start_timestamp": 1000,
+            "end_timestamp": 2000,
+            "buttons": [{ "label": "Button", "url": "https://example.com/" }],
+            "party": [1, 3]
+        }]"#,
+        false,
+    );
+
+    let (success, output) = check(&config, &[]);
+
+    assert!(success, "{}", output);
+    assert!(
+        output.contains("Message 1: OK (1 activities)."),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn limits_violations_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = write_config(
+        dir.path(),
+        "config.json",
+        r#"[{
+            "application_id": 42,
+            "state": "S",
+            "details": "Some details",
+            "large_image": { "key": "some key" },
+            "start_timestamp": 2000,
+            "end_timestamp": 1000,
+            "buttons": [
+                { "label": "One", "url": "example.com" },
+                { "label": "Two", "url": "https://example.com/" },
+                { "label": "Three", "url": "https://example.com/" }
+            ],
+            "party": [4, 3]
+        }]"#,
+        false,
+    );
+
+    let (success, output) = check(&config, &[]);
+
+    assert!(!success, "{}", output);
+
+    for violation in [
+        "Application 42: `state` is 1 characters long, but it must be from 2 to 128.",
+        "Application 42: `large_image.key` isn't a valid image key: `some key`.",
+        "Application 42: there are 3 buttons, but at most 2 are allowed.",
+        "Application 42: `buttons[0].url` isn't a valid URL: `example.com`.",
+        "Application 42: end timestamp 1000 is before start timestamp 2000.",
+        "Application 42: party size 4 of 3 is invalid.",
+    ] {
+        assert!(output.contains(violation), "{}", output);
+    }
+
+    assert!(!output.contains("details"), "{}", output);
+}
+
+#[test]
+fn identifiers_have_only_max_length() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = write_config(
+        dir.path(),
+        "config.json",
+        &format!(
+            r#"[
+                {{ "application_id": 1, "party": {{ "id": "p" }}, "secrets": {{ "join": "j" }} }},
+                {{ "application_id": 2, "secrets": {{ "match": "{}" }} }}
+            ]"#,
+            "m".repeat(129)
+        ),
+        false,
+    );
+
+    let (success, output) = check(&config, &[]);
+
+    assert!(!success, "{}", output);
+    assert!(!output.contains("Application 1"), "{}", output);
+    assert!(
+        output.contains(
+            "Application 2: `secrets.match` is 129 characters long, but it must be at most 128."
+        ),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn parse_errors_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = write_config(dir.path(), "config.json", "[{]", false);
+
+    let (success, output) = check(&config, &[]);
+
+    assert!(!success, "{}", output);
+    assert!(
+        output.contains("Error while parsing config file as Json"),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn executable_config_is_checked_for_given_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = write_config(
+        dir.path(),
+        "config.sh",
+        r#"#!/bin/sh
+echo '[{"application_id": 1, "state": "Valid"}]'
+echo '{"op": "patch", "application_id": 1, "details": "D"}'
+echo 'not json'
+sleep 60
+"#,
+        true,
+    );
+
+    let (success, output) = check(&config, &["--lines", "2"]);
+
+    assert!(!success, "{}", output);
+    assert!(
+        output.contains("Message 1: OK (1 activities)."),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("Application 1: `details` is 1 characters long"),
+        "{}",
+        output
+    );
+
+    let (success, output) = check(&config, &["--lines", "3"]);
+
+    assert!(!success, "{}", output);
+    assert!(
+        output.contains("Message 3: Error while parsing"),
+        "{}",
+        output
+    );
+
+    let (success, output) = check(&config, &["--lines", "1"]);
+
+    assert!(success, "{}", output);
+}
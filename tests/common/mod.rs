@@ -0,0 +1,441 @@
+/*
+    Copyright © 2021-2022 trickybestia <trickybestia@gmail.com>
+
+    This file is part of linux-discord-rich-presence.
+
+    linux-discord-rich-presence is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    linux-discord-rich-presence is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Test harness: a fake Discord IPC server and a helper that runs the daemon against it.
+
+#![allow(dead_code)]
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, OpenOptions},
+    io::{self, Read, Write},
+    os::unix::{
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde_json::{json, Value};
+use tempfile::{NamedTempFile, TempDir};
+
+pub const OP_HANDSHAKE: u32 = 0;
+pub const OP_FRAME: u32 = 1;
+pub const OP_CLOSE: u32 = 2;
+pub const OP_PING: u32 = 3;
+pub const OP_PONG: u32 = 4;
+
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A single frame received by [`MockDiscord`] from one of its clients.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// Sequential number of the connection the frame was received on.
+    pub connection: usize,
+    /// Application id sent by the client in its handshake.
+    pub client_id: String,
+    pub opcode: u32,
+    pub payload: Value,
+}
+
+impl Frame {
+    /// Returns `args.activity` if this is a SET_ACTIVITY command.
+    pub fn activity(&self) -> Option<&Value> {
+        if self.opcode == OP_FRAME && self.payload["cmd"] == "SET_ACTIVITY" {
+            Some(&self.payload["args"]["activity"])
+        } else {
+            None
+        }
+    }
+}
+
+fn read_frame(stream: &mut UnixStream) -> io::Result<(u32, Value)> {
+    let mut header = [0; 8];
+
+    stream.read_exact(&mut header)?;
+
+    let opcode = u32::from_le_bytes(header[..4].try_into().unwrap());
+    let length = u32::from_le_bytes(header[4..].try_into().unwrap());
+    let mut data = vec![0; length as usize];
+
+    stream.read_exact(&mut data)?;
+
+    let payload = serde_json::from_slice(&data)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    Ok((opcode, payload))
+}
+
+fn write_frame(stream: &mut UnixStream, opcode: u32, payload: &Value) -> io::Result<()> {
+    let data = payload.to_string();
+
+    stream.write_all(&opcode.to_le_bytes())?;
+    stream.write_all(&(data.len() as u32).to_le_bytes())?;
+    stream.write_all(data.as_bytes())
+}
+
+#[derive(Default)]
+struct Shared {
+    rejected: HashSet<String>,
+    /// Application ids whose clients never get replies, as if Discord hung.
+    muted: HashSet<String>,
+    /// Application ids whose clients get a late error response to an earlier command before
+    /// every reply.
+    stale: HashSet<String>,
+    /// Connected clients by application id. Writes to them happen under the lock, so
+    /// replies and dispatched events don't interleave.
+    clients: HashMap<String, UnixStream>,
+}
+
+fn serve_connection(
+    mut stream: UnixStream,
+    connection: usize,
+    frames: Sender<Frame>,
+    shared: Arc<Mutex<Shared>>,
+) {
+    let mut client_id = String::new();
+
+    while let Ok((opcode, payload)) = read_frame(&mut stream) {
+        let reply = match opcode {
+            OP_HANDSHAKE => {
+                client_id = payload["client_id"].as_str().unwrap_or_default().to_owned();
+
+                if shared.lock().unwrap().rejected.contains(&client_id) {
+                    let _ = frames.send(Frame {
+                        connection,
+                        client_id: client_id.clone(),
+                        opcode,
+                        payload,
+                    });
+                    let _ = write_frame(
+                        &mut stream,
+                        OP_CLOSE,
+                        &json!({ "code": 4000, "message": "Invalid Client ID" }),
+                    );
+
+                    break;
+                }
+
+                shared
+                    .lock()
+                    .unwrap()
+                    .clients
+                    .insert(client_id.clone(), stream.try_clone().unwrap());
+
+                Some((
+                    OP_FRAME,
+                    json!({
+                        "cmd": "DISPATCH",
+                        "evt": "READY",
+                        "data": {
+                            "v": 1,
+                            "user": { "id": "0", "username": "mock", "discriminator": "0" },
+                        },
+                    }),
+                ))
+            }
+            OP_FRAME => Some((
+                OP_FRAME,
+                json!({
+                    "cmd": payload["cmd"],
+                    "evt": null,
+                    "data": payload["args"]["activity"],
+                    "nonce": payload["nonce"],
+                }),
+            )),
+            OP_PING => Some((OP_PONG, payload.clone())),
+            _ => None,
+        };
+        let is_close = opcode == OP_CLOSE;
+        let is_command = opcode == OP_FRAME;
+
+        let frame = Frame {
+            connection,
+            client_id: client_id.clone(),
+            opcode,
+            payload,
+        };
+
+        if frames.send(frame).is_err() || is_close {
+            break;
+        }
+
+        // Clients may shut down their end right after sending a frame, so write errors are
+        // ignored to still receive whatever is left in the socket.
+        if let Some((opcode, payload)) = reply {
+            let shared = shared.lock().unwrap();
+
+            if shared.stale.contains(&client_id) && is_command {
+                let _ = write_frame(
+                    &mut stream,
+                    OP_FRAME,
+                    &json!({
+                        "cmd": payload["cmd"],
+                        "evt": "ERROR",
+                        "data": { "code": 1000, "message": "Stale response" },
+                        "nonce": "stale",
+                    }),
+                );
+            }
+
+            if !shared.muted.contains(&client_id) {
+                let _ = write_frame(&mut stream, opcode, &payload);
+            }
+        }
+    }
+
+    shared.lock().unwrap().clients.remove(&client_id);
+}
+
+/// Fake Discord client listening on `$XDG_RUNTIME_DIR/discord-ipc-0` inside a temporary directory.
+pub struct MockDiscord {
+    runtime_dir: TempDir,
+    frames: Receiver<Frame>,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl MockDiscord {
+    /// Creates the runtime directory without the socket, as if Discord wasn't started yet.
+    pub fn new() -> Self {
+        let (_, frames) = mpsc::channel();
+
+        Self {
+            runtime_dir: tempfile::tempdir().unwrap(),
+            frames,
+            shared: Arc::default(),
+        }
+    }
+
+    pub fn start() -> Self {
+        let mut discord = Self::new();
+
+        discord.listen();
+
+        discord
+    }
+
+    /// Creates the socket and starts accepting clients.
+    pub fn listen(&mut self) {
+        let listener = UnixListener::bind(self.runtime_dir.path().join("discord-ipc-0")).unwrap();
+        let (tx, rx) = mpsc::channel();
+        let shared = self.shared.clone();
+
+        thread::spawn(move || {
+            for (connection, stream) in listener.incoming().enumerate() {
+                let Ok(stream) = stream else { break };
+                let tx = tx.clone();
+                let shared = shared.clone();
+
+                thread::spawn(move || serve_connection(stream, connection, tx, shared));
+            }
+        });
+
+        self.frames = rx;
+    }
+
+    /// Makes handshakes with `client_id` fail the way Discord rejects unknown applications.
+    pub fn reject(&self, client_id: u64) {
+        self.shared
+            .lock()
+            .unwrap()
+            .rejected
+            .insert(client_id.to_string());
+    }
+
+    /// Makes clients of `client_id` get no replies at all.
+    pub fn mute(&self, client_id: u64) {
+        self.shared
+            .lock()
+            .unwrap()
+            .muted
+            .insert(client_id.to_string());
+    }
+
+    /// Makes clients of `client_id` get a late response to an earlier command before every
+    /// reply.
+    pub fn send_stale_responses(&self, client_id: u64) {
+        self.shared
+            .lock()
+            .unwrap()
+            .stale
+            .insert(client_id.to_string());
+    }
+
+    /// Sends a DISPATCH event to the connected client of `client_id`.
+    pub fn dispatch(&self, client_id: u64, event: &str, data: Value) {
+        let mut shared = self.shared.lock().unwrap();
+        let stream = shared
+            .clients
+            .get_mut(&client_id.to_string())
+            .expect("client isn't connected");
+
+        write_frame(
+            stream,
+            OP_FRAME,
+            &json!({ "cmd": "DISPATCH", "evt": event, "data": data }),
+        )
+        .unwrap();
+    }
+
+    pub fn runtime_dir(&self) -> &Path {
+        self.runtime_dir.path()
+    }
+
+    /// Waits for the next frame from any client.
+    pub fn next_frame(&self, timeout: Duration) -> Option<Frame> {
+        self.frames.recv_timeout(timeout).ok()
+    }
+
+    /// Waits for the next SET_ACTIVITY command, skipping handshakes and other frames.
+    pub fn next_activity(&self, timeout: Duration) -> Option<Frame> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let frame = self.next_frame(deadline.saturating_duration_since(Instant::now()))?;
+
+            if frame.activity().is_some() {
+                return Some(frame);
+            }
+        }
+    }
+
+    /// Asserts that no SET_ACTIVITY commands arrive for `duration`.
+    pub fn assert_no_activity(&self, duration: Duration) {
+        if let Some(frame) = self.next_activity(duration) {
+            panic!("Unexpected activity: {:?}", frame);
+        }
+    }
+
+    /// Asserts that no frames arrive for `duration`.
+    pub fn assert_silent(&self, duration: Duration) {
+        if let Some(frame) = self.next_frame(duration) {
+            panic!("Unexpected frame: {:?}", frame);
+        }
+    }
+}
+
+/// Writes `contents` to `name` inside `dir`, marking it executable when `executable` is set.
+pub fn write_config(dir: &Path, name: &str, contents: &str, executable: bool) -> PathBuf {
+    let path = dir.join(name);
+
+    fs::write(&path, contents).unwrap();
+
+    if executable {
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    path
+}
+
+/// Running daemon process, killed on drop.
+pub struct Daemon {
+    process: Child,
+    log: NamedTempFile,
+}
+
+impl Daemon {
+    pub fn spawn<I, S>(discord: &MockDiscord, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<std::ffi::OsStr>,
+    {
+        let log = NamedTempFile::new().unwrap();
+        // Both streams append to the same file, so neither overwrites the other.
+        let open_log = || OpenOptions::new().append(true).open(log.path()).unwrap();
+        let process = Command::new(env!("CARGO_BIN_EXE_linux-discord-rich-presence"))
+            .args(args)
+            .env("XDG_RUNTIME_DIR", discord.runtime_dir())
+            // Session bus is looked up in the runtime directory instead, where tests can
+            // start a private one.
+            .env_remove("DBUS_SESSION_BUS_ADDRESS")
+            .stdin(Stdio::null())
+            .stdout(open_log())
+            .stderr(open_log())
+            .spawn()
+            .unwrap();
+
+        Self { process, log }
+    }
+
+    pub fn with_config(discord: &MockDiscord, config: &Path) -> Self {
+        Self::spawn(discord, [Path::new("--config"), config])
+    }
+
+    /// Returns everything the daemon has logged so far, without terminal colors.
+    pub fn log(&self) -> String {
+        let log = fs::read_to_string(self.log.path()).unwrap();
+        let mut result = String::with_capacity(log.len());
+        let mut chars = log.chars();
+
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|c| *c == 'm');
+            } else {
+                result.push(c);
+            }
+        }
+
+        result
+    }
+
+    /// Waits until the daemon logs `text`, returning the whole log.
+    pub fn wait_for_log(&self, text: &str, timeout: Duration) -> String {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let log = self.log();
+
+            if log.contains(text) {
+                return log;
+            }
+
+            assert!(
+                Instant::now() < deadline,
+                "`{}` wasn't logged. Log:\n{}",
+                text,
+                log
+            );
+
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// Waits for the daemon to exit by itself, which it does only when it can't start.
+    pub fn wait(&mut self) -> ExitStatus {
+        self.process.wait().unwrap()
+    }
+
+    pub fn id(&self) -> u32 {
+        self.process.id()
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
@@ -0,0 +1,334 @@
+/*
+    Copyright © 2021-2022 trickybestia <trickybestia@gmail.com>
+
+    This file is part of linux-discord-rich-presence.
+
+    linux-discord-rich-presence is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    linux-discord-rich-presence is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+mod common;
+
+use std::{fs, os::unix::fs::PermissionsExt, process::Command, time::Duration};
+
+use serde_json::{json, Value};
+
+use common::{write_config, Daemon, MockDiscord, TIMEOUT};
+
+/// Runs `ctl` subcommand against the daemon of `discord`, returning whether it succeeded and
+/// its output.
+fn ctl(discord: &MockDiscord, args: &[&str]) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_linux-discord-rich-presence"))
+        .arg("ctl")
+        .args(args)
+        .env("XDG_RUNTIME_DIR", discord.runtime_dir())
+        .output()
+        .unwrap();
+
+    (
+        output.status.success(),
+        String::from_utf8(output.stdout).unwrap() + &String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+fn status(discord: &MockDiscord) -> Value {
+    let (success, output) = ctl(discord, &["status"]);
+
+    assert!(success, "{}", output);
+
+    serde_json::from_str(&output).unwrap()
+}
+
+#[test]
+fn ctl_sets_patches_and_clears_activities() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{"application_id": 1, "state": "Config"}]"#,
+        false,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    discord.next_activity(TIMEOUT).unwrap();
+
+    let (success, output) = ctl(
+        &discord,
+        &["set", r#"[{"application_id": 2, "state": "Control"}]"#],
+    );
+
+    assert!(success, "{}", output);
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.client_id, "2");
+    assert_eq!(frame.activity().unwrap()["state"], "Control");
+
+    let (success, output) = ctl(
+        &discord,
+        &[
+            "set",
+            r#"{"op": "patch", "application_id": 1, "state": "Patched"}"#,
+        ],
+    );
+
+    assert!(!success);
+    assert!(output.contains("Use `patch` command"), "{}", output);
+
+    let (success, output) = ctl(
+        &discord,
+        &[
+            "patch",
+            r#"{"op": "patch", "application_id": 2, "details": "Patched"}"#,
+        ],
+    );
+
+    assert!(success, "{}", output);
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.client_id, "2");
+    assert_eq!(
+        frame.activity().unwrap(),
+        &json!({ "state": "Control", "details": "Patched", "assets": {}, "timestamps": {} })
+    );
+    assert_eq!(
+        status(&discord),
+        json!({
+            "paused": false,
+            "sources": [{ "file": config }, "control_socket"],
+            "applications": [
+                { "application_id": 1, "state": "connected" },
+                { "application_id": 2, "state": "connected" },
+            ],
+        })
+    );
+
+    let (success, output) = ctl(&discord, &["clear"]);
+
+    assert!(success, "{}", output);
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.client_id, "2");
+    assert_eq!(frame.activity(), Some(&Value::Null));
+}
+
+#[test]
+fn ctl_overrides_activity_of_config() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{"application_id": 1, "state": "Config", "details": "Details"}]"#,
+        false,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    discord.next_activity(TIMEOUT).unwrap();
+
+    let (success, output) = ctl(
+        &discord,
+        &["set", r#"[{"application_id": 1, "state": "Control"}]"#],
+    );
+
+    assert!(success, "{}", output);
+    assert_eq!(
+        discord.next_activity(TIMEOUT).unwrap().activity().unwrap(),
+        &json!({ "state": "Control", "assets": {}, "timestamps": {} })
+    );
+
+    let (success, output) = ctl(
+        &discord,
+        &[
+            "patch",
+            r#"{"op": "patch", "application_id": 1, "details": "Patched"}"#,
+        ],
+    );
+
+    assert!(success, "{}", output);
+    assert_eq!(
+        discord.next_activity(TIMEOUT).unwrap().activity().unwrap(),
+        &json!({ "state": "Control", "details": "Patched", "assets": {}, "timestamps": {} })
+    );
+
+    assert!(ctl(&discord, &["clear"]).0);
+    assert_eq!(
+        discord.next_activity(TIMEOUT).unwrap().activity().unwrap(),
+        &json!({ "state": "Config", "details": "Details", "assets": {}, "timestamps": {} })
+    );
+}
+
+#[test]
+fn pause_clears_presence_until_resumed() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{"application_id": 1, "state": "Config"}]"#,
+        false,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    discord.next_activity(TIMEOUT).unwrap();
+
+    assert!(ctl(&discord, &["pause"]).0);
+    assert_eq!(
+        discord.next_activity(TIMEOUT).unwrap().activity(),
+        Some(&Value::Null)
+    );
+    assert_eq!(
+        status(&discord),
+        json!({
+            "paused": true,
+            "sources": [{ "file": config }],
+            "applications": [{ "application_id": 1, "state": "disconnected" }],
+        })
+    );
+
+    assert!(ctl(&discord, &["resume"]).0);
+    assert_eq!(
+        discord.next_activity(TIMEOUT).unwrap().activity().unwrap()["state"],
+        "Config"
+    );
+}
+
+#[test]
+fn reload_restarts_config_process() {
+    let discord = MockDiscord::start();
+    let state = discord.runtime_dir().join("state");
+
+    fs::write(&state, "One").unwrap();
+
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.sh",
+        &format!(
+            r#"#!/bin/sh
+echo "[{{\"application_id\": 1, \"state\": \"$(cat '{}')\"}}]"
+sleep 60
+"#,
+            state.display()
+        ),
+        true,
+    );
+    let daemon = Daemon::with_config(&discord, &config);
+
+    assert_eq!(
+        discord.next_activity(TIMEOUT).unwrap().activity().unwrap()["state"],
+        "One"
+    );
+
+    fs::write(&state, "Two").unwrap();
+
+    assert!(ctl(&discord, &["reload"]).0);
+    assert_eq!(
+        discord.next_activity(TIMEOUT).unwrap().activity().unwrap()["state"],
+        "Two"
+    );
+
+    daemon.wait_for_log("Reload was requested! Reloading...", TIMEOUT);
+}
+
+#[test]
+fn invalid_commands_are_rejected() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{"application_id": 1, "state": "Config"}]"#,
+        false,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    discord.next_activity(TIMEOUT).unwrap();
+
+    let (success, output) = ctl(&discord, &["set", "not json"]);
+
+    assert!(!success);
+    assert!(output.contains("Error while parsing JSON"), "{}", output);
+
+    let (success, output) = ctl(&discord, &["set", r#"{"state": "No activities"}"#]);
+
+    assert!(!success);
+    assert!(
+        output.contains("Error while parsing update message"),
+        "{}",
+        output
+    );
+
+    let (success, output) = ctl(
+        &discord,
+        &[
+            "patch",
+            r#"{"op": "patch", "application_id": 1, "secrets": {"join": "x"}, "buttons": [{"label": "B", "url": "https://example.com/"}]}"#,
+        ],
+    );
+
+    assert!(!success);
+    assert!(output.contains("Error while applying patch"), "{}", output);
+
+    discord.assert_no_activity(Duration::from_secs(1));
+}
+
+#[test]
+fn ctl_fails_without_daemon() {
+    let discord = MockDiscord::new();
+
+    let (success, output) = ctl(&discord, &["status"]);
+
+    assert!(!success);
+    assert!(
+        output.contains("Error while sending command to"),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn socket_is_private() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{"application_id": 1, "state": "Config"}]"#,
+        false,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    discord.next_activity(TIMEOUT).unwrap();
+
+    let metadata = fs::metadata(
+        discord
+            .runtime_dir()
+            .join("linux-discord-rich-presence.sock"),
+    )
+    .unwrap();
+
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+}
+
+#[test]
+fn ctl_requires_runtime_dir() {
+    let discord = MockDiscord::new();
+    let output = Command::new(env!("CARGO_BIN_EXE_linux-discord-rich-presence"))
+        .args(["ctl", "status"])
+        .env_remove("XDG_RUNTIME_DIR")
+        .env("TMPDIR", discord.runtime_dir())
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert!(!output.status.success());
+    assert!(stderr.contains("`--socket`"), "{}", stderr);
+}
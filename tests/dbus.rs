@@ -0,0 +1,305 @@
+/*
+    Copyright © 2021-2022 trickybestia <trickybestia@gmail.com>
+
+    This file is part of linux-discord-rich-presence.
+
+    linux-discord-rich-presence is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    linux-discord-rich-presence is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+mod common;
+
+use std::{
+    future::Future,
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use futures_util::StreamExt;
+use serde_json::{json, Value};
+use tokio::time::timeout;
+use zbus::{proxy::CacheProperties, Connection, Proxy};
+
+use common::{write_config, Daemon, MockDiscord, TIMEOUT};
+
+const BUS_NAME: &str = "org.linuxdiscordrichpresence.Daemon";
+const OBJECT_PATH: &str = "/org/linuxdiscordrichpresence/Daemon";
+
+/// Private session bus listening on `$XDG_RUNTIME_DIR/bus`, killed on drop.
+struct SessionBus {
+    process: Child,
+    address: String,
+}
+
+impl SessionBus {
+    /// Returns `None` if `dbus-daemon` isn't installed.
+    fn start(discord: &MockDiscord) -> Option<Self> {
+        let path = discord.runtime_dir().join("bus");
+        let address = format!("unix:path={}", path.display());
+        let process = match Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--nopidfile"])
+            .arg(format!("--address={}", address))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(process) => process,
+            Err(err) => {
+                eprintln!("Skipping test, can't start dbus-daemon: {}.", err);
+
+                return None;
+            }
+        };
+        let deadline = Instant::now() + TIMEOUT;
+
+        while !path.exists() {
+            assert!(Instant::now() < deadline, "dbus-daemon didn't start");
+
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        Some(Self { process, address })
+    }
+
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    async fn proxy(&self, cache_properties: CacheProperties) -> Proxy<'static> {
+        let connection = zbus::connection::Builder::address(self.address.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        proxy(&connection, cache_properties).await
+    }
+}
+
+impl Drop for SessionBus {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+async fn proxy(connection: &Connection, cache_properties: CacheProperties) -> Proxy<'static> {
+    zbus::proxy::Builder::new(connection)
+        .destination(BUS_NAME)
+        .unwrap()
+        .path(OBJECT_PATH)
+        .unwrap()
+        .interface(BUS_NAME)
+        .unwrap()
+        .cache_properties(cache_properties)
+        .build()
+        .await
+        .unwrap()
+}
+
+/// Waits until property `name` becomes `expected`.
+async fn wait_for_property<T>(proxy: &Proxy<'_>, name: &str, expected: T)
+where
+    T: TryFrom<zbus::zvariant::OwnedValue> + PartialEq + std::fmt::Debug,
+    T::Error: Into<zbus::Error>,
+{
+    let deadline = Instant::now() + TIMEOUT;
+
+    loop {
+        let value = proxy.get_property::<T>(name).await.unwrap();
+
+        if value == expected {
+            return;
+        }
+
+        assert!(
+            Instant::now() < deadline,
+            "{} is {:?} instead of {:?}",
+            name,
+            value,
+            expected
+        );
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[test]
+fn dbus_methods_change_presence() {
+    let discord = MockDiscord::start();
+    let Some(bus) = SessionBus::start(&discord) else {
+        return;
+    };
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{"application_id": 1, "state": "Config"}]"#,
+        false,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    discord.next_activity(TIMEOUT).unwrap();
+
+    bus.block_on(async {
+        let proxy = bus.proxy(CacheProperties::No).await;
+
+        proxy
+            .call_method(
+                "SetActivity",
+                &(r#"[{"application_id": 2, "state": "D-Bus"}]"#,),
+            )
+            .await
+            .unwrap();
+
+        let frame = discord.next_activity(TIMEOUT).unwrap();
+
+        assert_eq!(frame.client_id, "2");
+        assert_eq!(frame.activity().unwrap()["state"], "D-Bus");
+
+        wait_for_property(&proxy, "Connected", vec![1u64, 2]).await;
+
+        let activities: String = proxy.get_property("CurrentActivities").await.unwrap();
+
+        assert_eq!(
+            serde_json::from_str::<Value>(&activities).unwrap(),
+            json!([
+                { "application_id": 1, "state": "Config" },
+                { "application_id": 2, "state": "D-Bus" },
+            ])
+        );
+
+        proxy.call_method("Pause", &()).await.unwrap();
+        wait_for_property(&proxy, "Paused", true).await;
+        wait_for_property(&proxy, "Connected", Vec::<u64>::new()).await;
+
+        proxy.call_method("Resume", &()).await.unwrap();
+        wait_for_property(&proxy, "Connected", vec![1u64, 2]).await;
+
+        proxy
+            .call_method(
+                "SetActivity",
+                &(r#"[{"application_id": 1, "state": "Overridden"}]"#,),
+            )
+            .await
+            .unwrap();
+
+        // Frames of pause and resume may still be queued.
+        let deadline = Instant::now() + TIMEOUT;
+
+        loop {
+            let frame = discord
+                .next_activity(deadline.saturating_duration_since(Instant::now()))
+                .expect("activity of D-Bus didn't override the one of config");
+
+            if frame.client_id == "1" && frame.activity().unwrap()["state"] == "Overridden" {
+                break;
+            }
+        }
+
+        proxy.call_method("Clear", &()).await.unwrap();
+        wait_for_property(&proxy, "Connected", vec![1u64]).await;
+
+        let err = proxy
+            .call_method("SetActivity", &("not json",))
+            .await
+            .unwrap_err();
+
+        assert!(
+            err.to_string().contains("Error while parsing JSON"),
+            "{}",
+            err
+        );
+
+        let err = proxy
+            .call_method(
+                "SetActivity",
+                &(r#"{"op": "patch", "application_id": 1, "state": "Patched"}"#,),
+            )
+            .await
+            .unwrap_err();
+
+        assert!(
+            err.to_string()
+                .contains("D-Bus accepts only whole update messages"),
+            "{}",
+            err
+        );
+    });
+}
+
+#[test]
+fn dbus_properties_changes_are_signaled() {
+    let discord = MockDiscord::start();
+    let Some(bus) = SessionBus::start(&discord) else {
+        return;
+    };
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{"application_id": 1, "state": "Config"}]"#,
+        false,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    discord.next_activity(TIMEOUT).unwrap();
+    discord.reject(2);
+
+    bus.block_on(async {
+        let proxy = bus.proxy(CacheProperties::Yes).await;
+        let mut last_errors = proxy.receive_property_changed::<String>("LastError").await;
+
+        // The first item is the current value.
+        timeout(TIMEOUT, last_errors.next()).await.unwrap().unwrap();
+
+        proxy
+            .call_method("SetActivity", &(r#"[{"application_id": 2}]"#,))
+            .await
+            .unwrap();
+
+        let last_error = timeout(TIMEOUT, last_errors.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .get()
+            .await
+            .unwrap();
+
+        assert!(last_error == "Application 2: Error while connecting to Discord: `Invalid Client ID (code 4000)`.", "{}", last_error);
+    });
+}
+
+#[test]
+fn another_instance_doesnt_take_name_over() {
+    let discord = MockDiscord::start();
+    let Some(_bus) = SessionBus::start(&discord) else {
+        return;
+    };
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{"application_id": 1, "state": "Config"}]"#,
+        false,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    discord.next_activity(TIMEOUT).unwrap();
+
+    let other = Daemon::with_config(&discord, &config);
+
+    other.wait_for_log("Error while connecting to D-Bus session bus", TIMEOUT);
+}
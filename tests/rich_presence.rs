@@ -0,0 +1,1702 @@
+/*
+    Copyright © 2021-2022 trickybestia <trickybestia@gmail.com>
+
+    This file is part of linux-discord-rich-presence.
+
+    linux-discord-rich-presence is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    linux-discord-rich-presence is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+mod common;
+
+use std::{
+    fs,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde_json::{json, Value};
+
+use common::{write_config, Daemon, MockDiscord, OP_CLOSE, OP_HANDSHAKE, OP_PING, TIMEOUT};
+
+#[test]
+fn static_config_sets_activity() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{
+            "application_id": 42,
+            "state": "Some state",
+            "details": "Some details",
+            "large_image": { "key": "large", "text": "Large text" },
+            "small_image": { "key": "small" },
+            "start_timestamp": 1000,
+            "buttons": [{ "label": "Button", "url": "https://example.com/" }],
+            "party": [1, 3]
+        }]"#,
+        false,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    let handshake = discord.next_frame(TIMEOUT).unwrap();
+
+    assert_eq!(handshake.opcode, OP_HANDSHAKE);
+    assert_eq!(handshake.payload, json!({ "v": 1, "client_id": "42" }));
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.client_id, "42");
+    assert_eq!(
+        frame.activity().unwrap(),
+        &json!({
+            "state": "Some state",
+            "details": "Some details",
+            "timestamps": { "start": 1000 },
+            "party": { "size": [1, 3] },
+            "assets": {
+                "large_image": "large",
+                "large_text": "Large text",
+                "small_image": "small",
+            },
+            "buttons": [{ "label": "Button", "url": "https://example.com/" }],
+        })
+    );
+}
+
+#[test]
+fn executable_config_streams_activities() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.sh",
+        r#"#!/bin/sh
+echo '[{"application_id": 1, "state": "First"}]'
+sleep 1
+echo '[{"application_id": 1, "state": "Second"}]'
+sleep 60
+"#,
+        true,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    let first = discord.next_activity(TIMEOUT).unwrap();
+    let second = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(first.activity().unwrap()["state"], "First");
+    assert_eq!(second.activity().unwrap()["state"], "Second");
+    assert_eq!(first.connection, second.connection);
+}
+
+#[test]
+fn every_application_gets_own_connection() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[
+            { "application_id": 1, "state": "One" },
+            { "application_id": 2, "state": "Two" }
+        ]"#,
+        false,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    let mut activities = [
+        discord.next_activity(TIMEOUT).unwrap(),
+        discord.next_activity(TIMEOUT).unwrap(),
+    ];
+
+    activities.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+
+    assert_eq!(activities[0].client_id, "1");
+    assert_eq!(activities[0].activity().unwrap()["state"], "One");
+    assert_eq!(activities[1].client_id, "2");
+    assert_eq!(activities[1].activity().unwrap()["state"], "Two");
+    assert_ne!(activities[0].connection, activities[1].connection);
+}
+
+#[test]
+fn invalid_message_is_ignored() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.sh",
+        r#"#!/bin/sh
+echo 'not json'
+echo '[{"application_id": 1, "state": "Valid"}]'
+sleep 60
+"#,
+        true,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.activity().unwrap()["state"], "Valid");
+    discord.assert_silent(Duration::from_secs(1));
+}
+
+#[test]
+fn invalid_utf8_line_is_ignored() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.sh",
+        r#"#!/bin/sh
+printf '\377\n'
+echo '[{"application_id": 1, "state": "Valid"}]'
+sleep 60
+"#,
+        true,
+    );
+    let daemon = Daemon::with_config(&discord, &config);
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.activity().unwrap()["state"], "Valid");
+    assert!(
+        daemon.log().contains("Error while parsing config response"),
+        "{}",
+        daemon.log()
+    );
+}
+
+#[test]
+fn removed_application_is_cleared() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.sh",
+        r#"#!/bin/sh
+echo '[{"application_id": 1, "state": "One"}, {"application_id": 2, "state": "Two"}]'
+sleep 1
+echo '[{"application_id": 2, "state": "Two"}]'
+sleep 60
+"#,
+        true,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    discord.next_activity(TIMEOUT).unwrap();
+    discord.next_activity(TIMEOUT).unwrap();
+
+    let mut cleared = None;
+
+    while let Some(frame) = discord.next_frame(TIMEOUT) {
+        if frame.client_id != "1" {
+            continue;
+        }
+
+        if frame.opcode == OP_CLOSE {
+            break;
+        }
+
+        cleared = Some(frame);
+    }
+
+    assert_eq!(cleared.unwrap().activity(), Some(&Value::Null));
+}
+
+#[test]
+fn empty_message_clears_every_application() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.sh",
+        r#"#!/bin/sh
+echo '[{"application_id": 1, "state": "One"}, {"application_id": 2, "state": "Two"}]'
+sleep 1
+echo '[]'
+sleep 60
+"#,
+        true,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    discord.next_activity(TIMEOUT).unwrap();
+    discord.next_activity(TIMEOUT).unwrap();
+
+    let mut cleared = [
+        discord.next_activity(TIMEOUT).unwrap(),
+        discord.next_activity(TIMEOUT).unwrap(),
+    ];
+
+    cleared.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+
+    assert_eq!(cleared[0].client_id, "1");
+    assert_eq!(cleared[0].activity(), Some(&Value::Null));
+    assert_eq!(cleared[1].client_id, "2");
+    assert_eq!(cleared[1].activity(), Some(&Value::Null));
+}
+
+#[test]
+fn unchanged_activity_is_not_resent() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.sh",
+        r#"#!/bin/sh
+echo '[{"application_id": 1, "state": "Same"}]'
+echo '[{"application_id": 1, "state": "Same"}]'
+sleep 60
+"#,
+        true,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    discord.next_activity(TIMEOUT).unwrap();
+    discord.assert_no_activity(Duration::from_secs(2));
+}
+
+#[test]
+fn unchanged_activity_is_pinged() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{ "application_id": 1, "state": "Same" }]"#,
+        false,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    discord.next_activity(TIMEOUT).unwrap();
+
+    let frame = discord.next_frame(Duration::from_secs(15)).unwrap();
+
+    assert_eq!(frame.client_id, "1");
+    assert_eq!(frame.opcode, OP_PING);
+}
+
+#[test]
+fn failing_application_does_not_block_others() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[
+            { "application_id": 1, "state": "Rejected" },
+            { "application_id": 2, "state": "Accepted" }
+        ]"#,
+        false,
+    );
+
+    discord.reject(1);
+
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.client_id, "2");
+    assert_eq!(frame.activity().unwrap()["state"], "Accepted");
+
+    // Only the rejected application is retried, the other one is just pinged.
+    let mut frames = Vec::new();
+
+    while let Some(frame) = discord.next_frame(Duration::from_secs(15)) {
+        let is_retry = frame.client_id == "1" && frame.opcode == OP_HANDSHAKE;
+
+        frames.push(frame);
+
+        if is_retry {
+            break;
+        }
+    }
+
+    assert!(frames
+        .iter()
+        .any(|frame| frame.client_id == "1" && frame.opcode == OP_HANDSHAKE));
+    assert!(frames
+        .iter()
+        .all(|frame| frame.client_id != "2" || frame.opcode == OP_PING));
+}
+
+#[test]
+fn reconnects_when_socket_appears() {
+    let mut discord = MockDiscord::new();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{ "application_id": 1, "state": "Late" }]"#,
+        false,
+    );
+    let _daemon = Daemon::spawn(
+        &discord,
+        [
+            "--config".as_ref(),
+            config.as_os_str(),
+            "--backoff-initial".as_ref(),
+            "60".as_ref(),
+        ],
+    );
+
+    // Let the first attempt fail and the application back off for a minute.
+    thread::sleep(Duration::from_secs(1));
+    discord.listen();
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.activity().unwrap()["state"], "Late");
+}
+
+#[test]
+fn retries_back_off_exponentially() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{ "application_id": 1, "state": "Rejected" }]"#,
+        false,
+    );
+
+    discord.reject(1);
+
+    let _daemon = Daemon::spawn(
+        &discord,
+        [
+            "--config".as_ref(),
+            config.as_os_str(),
+            "--backoff-initial".as_ref(),
+            "0.5".as_ref(),
+            "--backoff-jitter".as_ref(),
+            "0".as_ref(),
+        ],
+    );
+
+    let mut attempts = Vec::new();
+
+    while attempts.len() < 4 {
+        let frame = discord.next_frame(TIMEOUT).unwrap();
+
+        if frame.opcode == OP_HANDSHAKE {
+            attempts.push(Instant::now());
+        }
+    }
+
+    let delays: Vec<_> = attempts.windows(2).map(|pair| pair[1] - pair[0]).collect();
+
+    assert!(delays[0] >= Duration::from_millis(400));
+    assert!(delays[1] >= Duration::from_millis(900));
+    assert!(delays[2] >= Duration::from_millis(1800));
+}
+
+#[test]
+fn backoff_which_would_spin_is_refused() {
+    let discord = MockDiscord::start();
+    let config = write_config(discord.runtime_dir(), "config.json", "[]", false);
+
+    for argument in [
+        "--backoff-multiplier=0",
+        "--backoff-multiplier=0.5",
+        "--backoff-jitter=1",
+        "--backoff-jitter=-0.1",
+    ] {
+        let mut daemon = Daemon::spawn(
+            &discord,
+            ["--config".as_ref(), config.as_os_str(), argument.as_ref()],
+        );
+
+        assert!(!daemon.wait().success(), "{} was accepted", argument);
+        assert!(daemon.log().contains("Invalid value"), "{}", daemon.log());
+    }
+}
+
+#[test]
+fn activity_type_is_sent() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{ "application_id": 1, "state": "Some song", "activity_type": "listening" }]"#,
+        false,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.activity().unwrap()["type"], 2);
+}
+
+#[test]
+fn party_secrets_and_instance_are_sent() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{
+            "application_id": 1,
+            "party": { "id": "party", "size": [2, 4] },
+            "secrets": { "join": "join", "spectate": "spectate", "match": "match" },
+            "instance": true
+        }]"#,
+        false,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+    let activity = frame.activity().unwrap();
+
+    assert_eq!(activity["party"], json!({ "id": "party", "size": [2, 4] }));
+    assert_eq!(
+        activity["secrets"],
+        json!({ "join": "join", "spectate": "spectate", "match": "match" })
+    );
+    assert_eq!(activity["instance"], true);
+}
+
+#[test]
+fn secrets_with_buttons_are_rejected() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.sh",
+        r#"#!/bin/sh
+echo '[{"application_id": 1, "secrets": {"join": "join"}, "buttons": [{"label": "Button", "url": "https://example.com/"}]}]'
+echo '[{"application_id": 1, "state": "Valid"}]'
+sleep 60
+"#,
+        true,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.activity().unwrap()["state"], "Valid");
+    assert_eq!(frame.activity().unwrap()["secrets"], Value::Null);
+}
+
+#[test]
+fn discord_events_are_forwarded_to_config_process() {
+    let discord = MockDiscord::start();
+    let events = discord.runtime_dir().join("events");
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.sh",
+        &format!(
+            r#"#!/bin/sh
+echo '[{{"application_id": 1, "secrets": {{"join": "secret"}}}}]'
+read line
+echo "$line" > {}
+echo '[{{"application_id": 1, "state": "Joined"}}]'
+sleep 60
+"#,
+            events.display()
+        ),
+        true,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    let subscriptions: Vec<_> = (0..3)
+        .map(|_| {
+            let frame = loop {
+                let frame = discord.next_frame(TIMEOUT).unwrap();
+
+                if frame.payload["cmd"] == "SUBSCRIBE" {
+                    break frame;
+                }
+            };
+
+            frame.payload["evt"].as_str().unwrap().to_owned()
+        })
+        .collect();
+
+    assert_eq!(
+        subscriptions,
+        [
+            "ACTIVITY_JOIN",
+            "ACTIVITY_SPECTATE",
+            "ACTIVITY_JOIN_REQUEST"
+        ]
+    );
+
+    discord.next_activity(TIMEOUT).unwrap();
+    discord.dispatch(1, "ACTIVITY_JOIN", json!({ "secret": "secret" }));
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.activity().unwrap()["state"], "Joined");
+
+    let event: Value = serde_json::from_str(&std::fs::read_to_string(events).unwrap()).unwrap();
+
+    assert_eq!(
+        event,
+        json!({
+            "type": "discord_event",
+            "application_id": 1,
+            "event": "ACTIVITY_JOIN",
+            "data": { "secret": "secret" },
+        })
+    );
+}
+
+/// Runs a config which sends `message` and records its stdin until the first ack, returning
+/// the recorded messages.
+fn read_stdin_messages(discord: &MockDiscord, message: &str) -> Vec<Value> {
+    let stdin = discord.runtime_dir().join("stdin");
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.sh",
+        &format!(
+            r#"#!/bin/sh
+echo '{}'
+while read line; do
+    echo "$line" >> {stdin}.part
+    case "$line" in *'"ack"'*) break;; esac
+done
+mv {stdin}.part {stdin}
+sleep 60
+"#,
+            message,
+            stdin = stdin.display()
+        ),
+        true,
+    );
+    let _daemon = Daemon::with_config(discord, &config);
+    let deadline = Instant::now() + TIMEOUT;
+
+    while !stdin.exists() {
+        assert!(Instant::now() < deadline, "Config Process got no ack");
+
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    std::fs::read_to_string(stdin)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn config_process_gets_state_and_ack() {
+    let discord = MockDiscord::start();
+    let messages = read_stdin_messages(&discord, r#"[{"application_id": 1, "state": "One"}]"#);
+
+    assert_eq!(
+        messages,
+        [
+            json!({ "type": "connection_state", "application_id": 1, "state": "connected" }),
+            json!({ "type": "ack", "applied": [1], "failed": [] }),
+        ]
+    );
+}
+
+#[test]
+fn config_process_gets_errors() {
+    let discord = MockDiscord::start();
+
+    discord.reject(1);
+
+    let messages = read_stdin_messages(
+        &discord,
+        r#"[{"application_id": 1, "state": "One"}, {"application_id": 2, "state": "Two"}]"#,
+    );
+
+    assert_eq!(messages.len(), 4);
+    assert_eq!(
+        messages[0],
+        json!({ "type": "connection_state", "application_id": 1, "state": "backing_off" })
+    );
+    assert_eq!(
+        messages[1],
+        json!({ "type": "connection_state", "application_id": 2, "state": "connected" })
+    );
+    assert_eq!(messages[2]["type"], "error");
+    assert_eq!(messages[2]["application_id"], 1);
+    assert_eq!(
+        messages[2]["message"],
+        "Error while connecting to Discord: `Invalid Client ID (code 4000)`."
+    );
+    assert_eq!(
+        messages[3],
+        json!({ "type": "ack", "applied": [2], "failed": [1] })
+    );
+}
+
+#[test]
+fn config_process_gets_failed_ack_for_invalid_patch() {
+    let discord = MockDiscord::start();
+    let messages = read_stdin_messages(
+        &discord,
+        r#"{"op": "patch", "application_id": 1, "secrets": {"join": "x"}, "buttons": [{"label": "B", "url": "https://example.com/"}]}"#,
+    );
+
+    assert_eq!(
+        messages,
+        [json!({ "type": "ack", "applied": [], "failed": [1] })]
+    );
+    discord.assert_no_activity(Duration::from_secs(1));
+}
+
+#[test]
+fn config_process_gets_only_its_own_acks() {
+    let discord = MockDiscord::start();
+    let dir = tempfile::tempdir().unwrap();
+
+    for (name, application_id, delay) in [("a.sh", 1, 0), ("b.sh", 2, 1)] {
+        write_config(
+            dir.path(),
+            name,
+            &format!(
+                r#"#!/bin/sh
+sleep {delay}
+echo '[{{"application_id": {application_id}, "state": "State"}}]'
+while read line; do
+    echo "$line" >> {stdin}
+done
+"#,
+                stdin = dir.path().join(format!("{}.stdin", name)).display(),
+            ),
+            true,
+        );
+    }
+
+    let _daemon = Daemon::spawn(&discord, [Path::new("--config-dir"), dir.path()]);
+
+    discord.next_activity(TIMEOUT).unwrap();
+    discord.next_activity(TIMEOUT).unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    // Acks list every shown application, not only the ones of their source.
+    for (name, applied) in [("a.sh", json!([1])), ("b.sh", json!([1, 2]))] {
+        let stdin = std::fs::read_to_string(dir.path().join(format!("{}.stdin", name))).unwrap();
+        let acks: Vec<Value> = stdin
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .filter(|message: &Value| message["type"] == "ack")
+            .collect();
+
+        assert_eq!(
+            acks,
+            [json!({ "type": "ack", "applied": applied, "failed": [] })],
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn config_process_stderr_is_logged() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.sh",
+        r#"#!/bin/sh
+echo "pid $$" >&2
+echo 'something went wrong' >&2
+exit 1
+"#,
+        true,
+    );
+    let daemon = Daemon::with_config(&discord, &config);
+    let log = daemon.wait_for_log("Last lines of Config Process' stderr", TIMEOUT);
+    let pid = log
+        .lines()
+        .find_map(|line| line.split("pid ").nth(1))
+        .unwrap();
+
+    assert!(log.contains(&format!(
+        "[WARN] Config Process `{}` ({}): something went wrong",
+        config.display(),
+        pid
+    )));
+    assert!(
+        log.contains(&format!("stderr:\npid {}\nsomething went wrong\n", pid)),
+        "{}",
+        log
+    );
+}
+
+#[test]
+fn failed_config_process_is_restarted() {
+    let discord = MockDiscord::start();
+    let counter = discord.runtime_dir().join("counter");
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.sh",
+        &format!(
+            r#"#!/bin/sh
+n=$(($(cat {counter} 2>/dev/null || echo 0) + 1))
+echo $n > {counter}
+[ $n -lt 3 ] && exit 1
+echo "[{{\"application_id\": 1, \"state\": \"Run $n\"}}]"
+sleep 60
+"#,
+            counter = counter.display()
+        ),
+        true,
+    );
+    let daemon = Daemon::spawn(
+        &discord,
+        [
+            "--config".as_ref(),
+            config.as_os_str(),
+            "--restart".as_ref(),
+            "on-failure".as_ref(),
+            "--restart-delay".as_ref(),
+            "0.1".as_ref(),
+        ],
+    );
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.activity().unwrap()["state"], "Run 3");
+    assert!(daemon
+        .log()
+        .contains("Config Process failed (exit status: 1)."));
+}
+
+#[test]
+fn config_process_is_not_restarted_by_default() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.sh",
+        r#"#!/bin/sh
+echo '[{"application_id": 1, "state": "Once"}]'
+kill -9 $$
+"#,
+        true,
+    );
+    let daemon = Daemon::with_config(&discord, &config);
+
+    discord.next_activity(TIMEOUT).unwrap();
+    daemon.wait_for_log("Config Process failed (signal: 9 (SIGKILL)).", TIMEOUT);
+    daemon.wait_for_log("permanently failed. Showing last sent activity.", TIMEOUT);
+    discord.assert_no_activity(Duration::from_secs(1));
+}
+
+#[test]
+fn permanently_failed_config_process_clears_activity() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.sh",
+        r#"#!/bin/sh
+echo '[{"application_id": 1, "state": "Failing"}]'
+exit 1
+"#,
+        true,
+    );
+    let daemon = Daemon::spawn(
+        &discord,
+        [
+            "--config",
+            config.to_str().unwrap(),
+            "--restart",
+            "always",
+            "--restart-max",
+            "2",
+            "--restart-delay",
+            "0.1",
+            "--clear-on-failure",
+        ],
+    );
+
+    let first = discord.next_activity(TIMEOUT).unwrap();
+    let cleared = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(first.activity().unwrap()["state"], "Failing");
+    assert_eq!(cleared.activity(), Some(&Value::Null));
+
+    let log = daemon.log();
+
+    assert!(log.contains("(restart 1 of 2)"));
+    assert!(log.contains("(restart 2 of 2)"));
+    assert!(log.contains("permanently failed. Clearing activity."));
+}
+
+#[test]
+fn missing_interpreter_is_reported_and_fixed_config_is_loaded() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.sh",
+        "#!/nonexistent/interpreter\n",
+        true,
+    );
+    let daemon = Daemon::with_config(&discord, &config);
+
+    daemon.wait_for_log(
+        &format!(
+            "Interpreter `/nonexistent/interpreter` from the shebang of `{}` wasn't found.",
+            config.display()
+        ),
+        TIMEOUT,
+    );
+
+    write_config(
+        discord.runtime_dir(),
+        "config.sh",
+        r#"#!/bin/sh
+echo '[{"application_id": 1, "state": "Fixed"}]'
+sleep 60
+"#,
+        true,
+    );
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.activity().unwrap()["state"], "Fixed");
+}
+
+#[test]
+fn invalid_executable_is_reported() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config",
+        "\x7fELF, but not really",
+        true,
+    );
+    let daemon = Daemon::with_config(&discord, &config);
+
+    daemon.wait_for_log(
+        &format!(
+            "`{}` isn't a valid executable. Check its shebang or binary format.",
+            config.display()
+        ),
+        TIMEOUT,
+    );
+}
+
+#[test]
+fn config_is_reloaded_while_neighbour_keeps_changing() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{ "application_id": 42, "state": "First" }]"#,
+        false,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    discord.next_activity(TIMEOUT).unwrap();
+
+    let neighbour = discord.runtime_dir().join("neighbour.log");
+    let config_path = config.clone();
+    let writer = thread::spawn(move || {
+        for i in 0..100 {
+            fs::write(&neighbour, i.to_string()).unwrap();
+
+            if i == 5 {
+                fs::write(
+                    &config_path,
+                    r#"[{ "application_id": 42, "state": "Second" }]"#,
+                )
+                .unwrap();
+            }
+
+            thread::sleep(Duration::from_millis(50));
+        }
+    });
+
+    let frame = discord.next_activity(Duration::from_secs(4)).unwrap();
+
+    assert_eq!(frame.activity().unwrap()["state"], "Second");
+
+    writer.join().unwrap();
+}
+
+#[test]
+fn config_replaced_by_rename_is_reloaded() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{ "application_id": 42, "state": "First" }]"#,
+        false,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.activity().unwrap()["state"], "First");
+
+    // Editors save by writing a temporary file and renaming it over the original, so the
+    // config must keep being watched after every replacement.
+    for state in ["Second", "Third"] {
+        let temporary = write_config(
+            discord.runtime_dir(),
+            ".config.json.swp",
+            &format!(r#"[{{ "application_id": 42, "state": "{}" }}]"#, state),
+            false,
+        );
+
+        fs::rename(temporary, &config).unwrap();
+
+        let frame = discord.next_activity(TIMEOUT).unwrap();
+
+        assert_eq!(frame.activity().unwrap()["state"], state);
+    }
+}
+
+#[test]
+fn removed_config_keeps_activity_until_recreated() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{ "application_id": 42, "state": "First" }]"#,
+        false,
+    );
+    let daemon = Daemon::with_config(&discord, &config);
+
+    discord.next_activity(TIMEOUT).unwrap();
+
+    fs::remove_file(&config).unwrap();
+
+    daemon.wait_for_log("Config file was removed!", TIMEOUT);
+    discord.assert_no_activity(Duration::from_secs(1));
+
+    write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{ "application_id": 42, "state": "Second" }]"#,
+        false,
+    );
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.activity().unwrap()["state"], "Second");
+}
+
+#[test]
+fn invalid_static_config_keeps_previous_one() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{ "application_id": 42, "state": "First" }]"#,
+        false,
+    );
+    let daemon = Daemon::with_config(&discord, &config);
+
+    discord.next_activity(TIMEOUT).unwrap();
+
+    write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{ "application_id": 42, "sta"#,
+        false,
+    );
+
+    daemon.wait_for_log("Keeping previous config.", TIMEOUT);
+    discord.assert_no_activity(Duration::from_secs(1));
+
+    write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{ "application_id": 42, "state": "Second" }]"#,
+        false,
+    );
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.activity().unwrap()["state"], "Second");
+}
+
+#[test]
+fn config_process_keeps_running_when_new_one_fails_to_start() {
+    let discord = MockDiscord::start();
+    let pid_file = discord.runtime_dir().join("config.pid");
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.sh",
+        &format!(
+            r#"#!/bin/sh
+echo $$ > '{}'
+echo '[{{"application_id": 1, "state": "Running"}}]'
+exec sleep 60
+"#,
+            pid_file.display()
+        ),
+        true,
+    );
+    let daemon = Daemon::with_config(&discord, &config);
+
+    discord.next_activity(TIMEOUT).unwrap();
+
+    let pid = fs::read_to_string(&pid_file).unwrap();
+
+    write_config(
+        discord.runtime_dir(),
+        "config.sh",
+        "#!/nonexistent/interpreter\n",
+        true,
+    );
+
+    daemon.wait_for_log("Keeping previous config.", TIMEOUT);
+
+    assert!(
+        Path::new("/proc").join(pid.trim()).exists(),
+        "Previous Config Process was killed"
+    );
+    discord.assert_silent(Duration::from_secs(1));
+}
+
+#[test]
+fn config_dir_merges_sources_and_follows_files() {
+    let discord = MockDiscord::start();
+    let dir = tempfile::tempdir().unwrap();
+
+    write_config(
+        dir.path(),
+        "music.json",
+        r#"[{ "application_id": 1, "state": "Music" }]"#,
+        false,
+    );
+    write_config(
+        dir.path(),
+        "editor.sh",
+        r#"#!/bin/sh
+echo '[{"application_id": 2, "state": "Editor"}]'
+sleep 60
+"#,
+        true,
+    );
+    // Editors' leftovers aren't configs.
+    write_config(dir.path(), ".music.json.swp", "garbage", false);
+
+    let _daemon = Daemon::spawn(&discord, [Path::new("--config-dir"), dir.path()]);
+
+    let mut states = [
+        discord.next_activity(TIMEOUT).unwrap(),
+        discord.next_activity(TIMEOUT).unwrap(),
+    ]
+    .map(|frame| frame.activity().unwrap()["state"].clone());
+
+    states.sort_by_key(|state| state.to_string());
+
+    assert_eq!(states, [json!("Editor"), json!("Music")]);
+
+    write_config(
+        dir.path(),
+        "game.json",
+        r#"[{ "application_id": 3, "state": "Game" }]"#,
+        false,
+    );
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.client_id, "3");
+    assert_eq!(frame.activity().unwrap()["state"], "Game");
+
+    fs::remove_file(dir.path().join("music.json")).unwrap();
+
+    let mut cleared = None;
+
+    while let Some(frame) = discord.next_frame(TIMEOUT) {
+        if frame.client_id != "1" {
+            continue;
+        }
+
+        if frame.opcode == OP_CLOSE {
+            break;
+        }
+
+        cleared = Some(frame);
+    }
+
+    assert_eq!(cleared.unwrap().activity(), Some(&Value::Null));
+    discord.assert_no_activity(Duration::from_secs(1));
+}
+
+#[test]
+fn config_and_config_dir_conflict() {
+    let discord = MockDiscord::start();
+    let mut daemon = Daemon::spawn(&discord, ["--config", "a.json", "--config-dir", "configs"]);
+
+    assert!(!daemon.wait().success());
+}
+
+#[test]
+fn higher_priority_source_overlays_activity() {
+    let discord = MockDiscord::start();
+    let dir = tempfile::tempdir().unwrap();
+
+    write_config(
+        dir.path(),
+        "coding.json",
+        r#"[{ "application_id": 1, "state": "Coding", "details": "main.rs" }]"#,
+        false,
+    );
+
+    let _daemon = Daemon::spawn(&discord, [Path::new("--config-dir"), dir.path()]);
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.activity().unwrap()["state"], "Coding");
+
+    write_config(
+        dir.path(),
+        "game.sh",
+        r#"#!/bin/sh
+echo '{"priority": 10, "merge": "overlay", "activities": [{"application_id": 1, "state": "Playing"}]}'
+sleep 1
+echo '[]'
+sleep 60
+"#,
+        true,
+    );
+
+    let activity = discord.next_activity(TIMEOUT).unwrap().activity().cloned();
+
+    assert_eq!(
+        activity,
+        Some(json!({ "state": "Playing", "details": "main.rs", "assets": {}, "timestamps": {} }))
+    );
+
+    let activity = discord.next_activity(TIMEOUT).unwrap().activity().cloned();
+
+    assert_eq!(
+        activity,
+        Some(json!({ "state": "Coding", "details": "main.rs", "assets": {}, "timestamps": {} }))
+    );
+}
+
+#[test]
+fn replacing_source_hides_lower_priority_ones() {
+    let discord = MockDiscord::start();
+    let dir = tempfile::tempdir().unwrap();
+
+    write_config(
+        dir.path(),
+        "coding.json",
+        r#"[{ "application_id": 1, "state": "Coding" }]"#,
+        false,
+    );
+
+    let _daemon = Daemon::spawn(&discord, [Path::new("--config-dir"), dir.path()]);
+
+    discord.next_activity(TIMEOUT).unwrap();
+
+    write_config(
+        dir.path(),
+        "game.json",
+        r#"{
+            "priority": 10,
+            "merge": "replace",
+            "activities": [{ "application_id": 2, "state": "Playing" }]
+        }"#,
+        false,
+    );
+
+    let mut frames = [
+        discord.next_activity(TIMEOUT).unwrap(),
+        discord.next_activity(TIMEOUT).unwrap(),
+    ];
+
+    frames.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+
+    assert_eq!(frames[0].client_id, "1");
+    assert_eq!(frames[0].activity(), Some(&Value::Null));
+    assert_eq!(frames[1].client_id, "2");
+    assert_eq!(frames[1].activity().unwrap()["state"], "Playing");
+}
+
+#[test]
+fn patches_change_last_activities() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.sh",
+        r#"#!/bin/sh
+echo '[{"application_id": 1, "state": "One", "details": "Details"}, {"application_id": 2, "state": "Two"}]'
+sleep 1
+echo '{"op": "patch", "application_id": 1, "state": "Patched"}'
+echo '{"op": "patch", "application_id": 1, "secrets": {"join": "x"}, "buttons": [{"label": "B", "url": "https://example.com/"}]}'
+sleep 1
+echo '{"op": "remove", "application_id": 2}'
+sleep 60
+"#,
+        true,
+    );
+    let daemon = Daemon::with_config(&discord, &config);
+
+    discord.next_activity(TIMEOUT).unwrap();
+    discord.next_activity(TIMEOUT).unwrap();
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.client_id, "1");
+    assert_eq!(
+        frame.activity().unwrap(),
+        &json!({ "state": "Patched", "details": "Details", "assets": {}, "timestamps": {} })
+    );
+
+    daemon.wait_for_log(
+        "Error while applying patch: `application 1 has both secrets and buttons",
+        TIMEOUT,
+    );
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.client_id, "2");
+    assert_eq!(frame.activity(), Some(&Value::Null));
+    discord.assert_no_activity(Duration::from_secs(1));
+}
+
+#[test]
+fn toml_config_sets_activity() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.toml",
+        r#"# Shown while coding.
+priority = 1
+
+[[activities]]
+application_id = 42
+state = "Some state"
+party = { id = "party", size = [1, 3] }
+
+[activities.large_image]
+key = "large"
+"#,
+        false,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(
+        frame.activity().unwrap(),
+        &json!({
+            "state": "Some state",
+            "party": { "id": "party", "size": [1, 3] },
+            "assets": { "large_image": "large" },
+            "timestamps": {},
+        })
+    );
+}
+
+#[test]
+fn yaml_config_is_detected_by_contents() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "linux-discord-rich-presencerc",
+        r#"# Shown while coding.
+- application_id: 42
+  state: Some state
+  buttons:
+    - label: Button
+      url: https://example.com/
+"#,
+        false,
+    );
+    let _daemon = Daemon::with_config(&discord, &config);
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(
+        frame.activity().unwrap(),
+        &json!({
+            "state": "Some state",
+            "buttons": [{ "label": "Button", "url": "https://example.com/" }],
+            "assets": {},
+            "timestamps": {},
+        })
+    );
+}
+
+#[test]
+fn config_errors_report_position() {
+    let discord = MockDiscord::start();
+    let toml = write_config(
+        discord.runtime_dir(),
+        "config.toml",
+        "[[activities]]\napplication_id = 42\nstate = 5\n",
+        false,
+    );
+    let daemon = Daemon::with_config(&discord, &toml);
+
+    daemon.wait_for_log("line 3", TIMEOUT);
+
+    let yaml = write_config(
+        discord.runtime_dir(),
+        "config.yaml",
+        "- application_id: 42\n  state: [1]\n",
+        false,
+    );
+    let daemon = Daemon::with_config(&discord, &yaml);
+
+    daemon.wait_for_log("line 2 column", TIMEOUT);
+}
+
+#[test]
+fn templates_are_expanded_periodically() {
+    let discord = MockDiscord::start();
+    let value = discord.runtime_dir().join("value");
+
+    fs::write(&value, "First\n").unwrap();
+
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.yaml",
+        &format!(
+            r#"- application_id: 42
+  state: "{{cmd:cat '{}'}} on {{hostname}}"
+  details: "{{env.XDG_RUNTIME_DIR}} {{not a template}}"
+  large_image:
+    key: "{{kernel}}"
+  start_timestamp: "{{uptime_start}}"
+"#,
+            value.display()
+        ),
+        false,
+    );
+    let _daemon = Daemon::spawn(
+        &discord,
+        [
+            Path::new("--config"),
+            &config,
+            Path::new("--template-interval"),
+            Path::new("0.5"),
+        ],
+    );
+
+    let hostname = fs::read_to_string("/proc/sys/kernel/hostname").unwrap();
+    let kernel = fs::read_to_string("/proc/sys/kernel/osrelease").unwrap();
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+    let activity = frame.activity().unwrap();
+
+    assert_eq!(
+        activity["state"],
+        format!("First on {}", hostname.trim_end())
+    );
+    assert_eq!(
+        activity["details"],
+        format!("{} {{not a template}}", discord.runtime_dir().display())
+    );
+    assert_eq!(activity["assets"]["large_image"], kernel.trim_end());
+    assert!(activity["timestamps"]["start"].as_i64().unwrap() > 0);
+
+    // Expansions which give the same activity aren't sent.
+    discord.assert_no_activity(Duration::from_secs(1));
+
+    fs::write(&value, "Second\n").unwrap();
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(
+        frame.activity().unwrap()["state"],
+        format!("Second on {}", hostname.trim_end())
+    );
+}
+
+#[test]
+fn stderr_of_template_commands_is_logged() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{"application_id": 42, "state": "{cmd:echo Oops >&2; echo State}"}]"#,
+        false,
+    );
+    let daemon = Daemon::with_config(&discord, &config);
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.activity().unwrap()["state"], "State");
+
+    daemon.wait_for_log(
+        "Template command `echo Oops >&2; echo State`: Oops",
+        TIMEOUT,
+    );
+}
+
+#[test]
+fn activities_are_sanitized_leniently_by_default() {
+    let discord = MockDiscord::start();
+    // 👍🏽 is a single grapheme of two characters, which mustn't be split.
+    let details = format!("{}👍🏽", "d".repeat(127));
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        &json!([{
+            "application_id": 42,
+            "state": "S",
+            "details": details,
+            "buttons": [
+                { "label": "Invalid", "url": "ftp://example.com/" },
+                { "label": "L".repeat(40), "url": "https://example.com/1" },
+                { "label": "Two", "url": "https://example.com/2" },
+                { "label": "Three", "url": "https://example.com/3" },
+            ],
+        }])
+        .to_string(),
+        false,
+    );
+    let daemon = Daemon::with_config(&discord, &config);
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+    let activity = frame.activity().unwrap();
+
+    assert_eq!(activity["state"], "S\u{2800}");
+    assert_eq!(activity["details"], "d".repeat(127));
+    assert_eq!(
+        activity["buttons"],
+        json!([
+            { "label": "L".repeat(32), "url": "https://example.com/1" },
+            { "label": "Two", "url": "https://example.com/2" },
+        ])
+    );
+
+    daemon.wait_for_log(
+        "Activity of application 42 was changed to fit Discord's limits: `state` was padded",
+        TIMEOUT,
+    );
+}
+
+#[test]
+fn identifiers_are_not_changed_by_sanitization() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        &json!([{
+            "application_id": 42,
+            "state": "State",
+            "party": { "id": "p" },
+            "secrets": { "join": "j", "spectate": "s".repeat(129) },
+        }])
+        .to_string(),
+        false,
+    );
+    let daemon = Daemon::with_config(&discord, &config);
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+    let activity = frame.activity().unwrap();
+
+    assert_eq!(activity["party"], json!({ "id": "p" }));
+    assert_eq!(activity["secrets"], json!({ "join": "j" }));
+
+    daemon.wait_for_log(
+        "`secrets.spectate` was removed, since it's longer than 128 characters",
+        TIMEOUT,
+    );
+}
+
+#[test]
+fn strict_sanitization_rejects_activities() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[
+            { "application_id": 1, "state": "S" },
+            { "application_id": 2, "state": "Valid" }
+        ]"#,
+        false,
+    );
+    let daemon = Daemon::spawn(
+        &discord,
+        [
+            Path::new("--config"),
+            &config,
+            Path::new("--sanitize"),
+            Path::new("strict"),
+        ],
+    );
+
+    let frame = discord.next_activity(TIMEOUT).unwrap();
+
+    assert_eq!(frame.client_id, "2");
+
+    daemon.wait_for_log(
+        "Application 1: Activity violates Discord's limits: `state` is 1 characters long",
+        TIMEOUT,
+    );
+    discord.assert_no_activity(Duration::from_secs(1));
+}
+
+#[test]
+fn timestamps_accept_textual_forms() {
+    let discord = MockDiscord::start();
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.sh",
+        r#"#!/bin/sh
+echo '[{"application_id": 1, "start_timestamp": 1000, "end_timestamp": 2000}]'
+echo '[{"application_id": 1, "start_timestamp": "now", "end_timestamp": "+25m"}]'
+echo '[{"application_id": 1, "start_timestamp": "2026-01-01T00:00:00Z", "end_timestamp": "2026-01-01T01:30:00+01:00"}]'
+echo '[{"application_id": 1, "start_timestamp": "daemon_start", "end_timestamp": "+1h30m"}]'
+echo '[{"application_id": 1, "start_timestamp": "-1h", "end_timestamp": "+90"}]'
+echo '[{"application_id": 1, "start_timestamp": "yesterday"}]'
+sleep 60
+"#,
+        true,
+    );
+    let started = now();
+    let daemon = Daemon::with_config(&discord, &config);
+
+    let timestamps = |frame: common::Frame| frame.activity().unwrap()["timestamps"].clone();
+
+    assert_eq!(
+        timestamps(discord.next_activity(TIMEOUT).unwrap()),
+        json!({ "start": 1000, "end": 2000 })
+    );
+
+    let relative = timestamps(discord.next_activity(TIMEOUT).unwrap());
+    let start = relative["start"].as_i64().unwrap();
+
+    assert!((started..started + 5).contains(&start), "{}", relative);
+    assert_eq!(relative["end"].as_i64().unwrap(), start + 25 * 60);
+
+    assert_eq!(
+        timestamps(discord.next_activity(TIMEOUT).unwrap()),
+        json!({ "start": 1767225600, "end": 1767227400 })
+    );
+
+    let relative = timestamps(discord.next_activity(TIMEOUT).unwrap());
+    let start = relative["start"].as_i64().unwrap();
+
+    assert!((started..started + 5).contains(&start), "{}", relative);
+    assert!(relative["end"].as_i64().unwrap() >= start + 90 * 60);
+
+    let relative = timestamps(discord.next_activity(TIMEOUT).unwrap());
+    let start = relative["start"].as_i64().unwrap();
+
+    assert!(
+        (started - 60 * 60..started - 60 * 60 + 5).contains(&start),
+        "{}",
+        relative
+    );
+    // Both offsets are relative to now, which may tick in between.
+    assert!(
+        (60 * 60 + 90..=60 * 60 + 91).contains(&(relative["end"].as_i64().unwrap() - start)),
+        "{}",
+        relative
+    );
+
+    daemon.wait_for_log("invalid value: string \"yesterday\"", TIMEOUT);
+}
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+#[test]
+fn config_changes_are_handled_while_discord_hangs() {
+    let discord = MockDiscord::start();
+
+    discord.mute(1);
+
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{"application_id": 1, "state": "One"}]"#,
+        false,
+    );
+    let daemon = Daemon::with_config(&discord, &config);
+
+    // Wait for the handshake, whose reply never comes.
+    discord.next_frame(TIMEOUT).unwrap();
+
+    write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{"application_id": 1, "state": "Two"}]"#,
+        false,
+    );
+
+    daemon.wait_for_log(
+        "Config file was changed! Reloading...",
+        Duration::from_secs(2),
+    );
+}
+
+#[test]
+fn late_responses_are_skipped() {
+    let discord = MockDiscord::start();
+
+    discord.send_stale_responses(1);
+
+    let config = write_config(
+        discord.runtime_dir(),
+        "config.json",
+        r#"[{"application_id": 1, "state": "One"}]"#,
+        false,
+    );
+    let daemon = Daemon::with_config(&discord, &config);
+
+    discord.next_activity(TIMEOUT).unwrap();
+
+    let log = daemon.wait_for_log("Application 1: connected to Discord.", TIMEOUT);
+
+    assert!(!log.contains("Stale response"), "{}", log);
+}
+
+/// Starts the daemon with a config directory where one file sets secrets and another one
+/// overlays buttons over them.
+fn spawn_with_overlaid_buttons(discord: &MockDiscord, dir: &Path, args: &[&str]) -> Daemon {
+    write_config(
+        dir,
+        "game.json",
+        r#"[{ "application_id": 1, "state": "Playing", "secrets": { "join": "secret" } }]"#,
+        false,
+    );
+
+    let daemon = Daemon::spawn(
+        discord,
+        [Path::new("--config-dir"), dir]
+            .into_iter()
+            .chain(args.iter().map(Path::new)),
+    );
+
+    discord.next_activity(TIMEOUT).unwrap();
+
+    write_config(
+        dir,
+        "links.json",
+        r#"{"priority": 10, "merge": "overlay", "activities": [{"application_id": 1, "buttons": [{"label": "Site", "url": "https://example.com/"}]}]}"#,
+        false,
+    );
+
+    daemon
+}
+
+#[test]
+fn merged_secrets_and_buttons_are_sanitized() {
+    let discord = MockDiscord::start();
+    let dir = tempfile::tempdir().unwrap();
+    let daemon = spawn_with_overlaid_buttons(&discord, dir.path(), &[]);
+
+    daemon.wait_for_log(
+        "buttons were removed, since Discord doesn't allow them with secrets",
+        TIMEOUT,
+    );
+    discord.assert_no_activity(Duration::from_secs(1));
+}
+
+#[test]
+fn merged_secrets_and_buttons_are_rejected_strictly() {
+    let discord = MockDiscord::start();
+    let dir = tempfile::tempdir().unwrap();
+    let daemon = spawn_with_overlaid_buttons(&discord, dir.path(), &["--sanitize", "strict"]);
+
+    assert_eq!(
+        discord.next_activity(TIMEOUT).unwrap().activity(),
+        Some(&Value::Null)
+    );
+    daemon.wait_for_log(
+        "there are both secrets and buttons, which Discord doesn't allow",
+        TIMEOUT,
+    );
+}
@@ -0,0 +1,66 @@
+/*
+    Copyright © 2021-2022 trickybestia <trickybestia@gmail.com>
+
+    This file is part of linux-discord-rich-presence.
+
+    linux-discord-rich-presence is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    linux-discord-rich-presence is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with linux-discord-rich-presence.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::{fs, path::Path, process::Command};
+
+use serde_json::Value;
+
+#[test]
+fn checked_in_schema_matches_types() {
+    let output = Command::new(env!("CARGO_BIN_EXE_linux-discord-rich-presence"))
+        .arg("schema")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+
+    let schema = String::from_utf8(output.stdout).unwrap();
+    let checked_in =
+        fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("doc/update.schema.json"))
+            .unwrap();
+
+    assert!(
+        schema == checked_in,
+        "doc/update.schema.json is outdated. Regenerate it with \
+        `cargo run -- schema > doc/update.schema.json`."
+    );
+}
+
+#[test]
+fn schema_is_strict() {
+    let output = Command::new(env!("CARGO_BIN_EXE_linux-discord-rich-presence"))
+        .arg("schema")
+        .output()
+        .unwrap();
+    let schema = serde_json::from_slice::<Value>(&output.stdout).unwrap();
+    let definitions = &schema["$defs"];
+
+    for name in ["UpdateMessageItem", "Button", "Image", "Secrets"] {
+        assert_eq!(
+            definitions[name]["additionalProperties"], false,
+            "{} allows unknown fields",
+            name
+        );
+    }
+
+    assert_eq!(
+        definitions["UpdateMessageItem"]["properties"]["buttons"]["maxItems"],
+        2
+    );
+}